@@ -0,0 +1,105 @@
+use std::hint::black_box;
+
+use connect_4::bitboard::{BitBoard, Piece};
+use connect_4::minimax::evaluate_heuristic;
+use connect_4::params::EvalParams;
+use connect_4::rng::Rng;
+use connect_4::search::Searcher;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+// fixed position set: seeded random games stopped after `plies` moves (AI to move, game not over)
+fn positions() -> Vec<(String, BitBoard)> {
+    let specs = [
+        ("6x7-ply1", 6, 7, 4, 1),
+        ("6x7-ply9", 6, 7, 4, 9),
+        ("6x7-ply19", 6, 7, 4, 19),
+        ("10x11-ply11", 10, 11, 5, 11),
+    ];
+
+    specs
+        .iter()
+        .map(|&(name, rows, cols, connect, plies)| (name.to_string(), random_position(rows, cols, connect, plies)))
+        .collect()
+}
+
+fn random_position(rows: u8, cols: u8, connect: u8, plies: usize) -> BitBoard {
+    let mut rng = Rng::new(2024);
+
+    loop {
+        let mut board = BitBoard::new(rows, cols, connect);
+        let mut piece = Piece::Player;
+
+        for _ in 0..plies {
+            let valid = board.get_valid_locations();
+            board = board.drop_piece(valid[rng.below(valid.len())], piece).unwrap();
+            piece = if piece == Piece::Player { Piece::AI } else { Piece::Player };
+        }
+
+        if !board.check_win(Piece::Player) && !board.check_win(Piece::AI) && !board.is_full() {
+            return board;
+        }
+    }
+}
+
+fn bench_drop_piece(c: &mut Criterion) {
+    let mut group = c.benchmark_group("drop_piece");
+    for (name, board) in positions() {
+        let valid = board.get_valid_locations();
+        group.throughput(Throughput::Elements(valid.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(&name), &board, |b, board| {
+            b.iter(|| {
+                for &col in &valid {
+                    black_box(board.drop_piece(black_box(col), Piece::AI));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_check_win(c: &mut Criterion) {
+    let mut group = c.benchmark_group("check_win");
+    for (name, board) in positions() {
+        group.bench_with_input(BenchmarkId::from_parameter(&name), &board, |b, board| {
+            b.iter(|| black_box(board.check_win(black_box(Piece::AI))) | black_box(board.check_win(black_box(Piece::Player))))
+        });
+    }
+    group.finish();
+}
+
+fn bench_evaluate(c: &mut Criterion) {
+    let params = EvalParams::default();
+    let mut group = c.benchmark_group("evaluate_heuristic");
+    for (name, board) in positions() {
+        group.bench_with_input(BenchmarkId::from_parameter(&name), &board, |b, board| {
+            b.iter(|| evaluate_heuristic(black_box(board), Piece::AI, Piece::AI, &params))
+        });
+    }
+    group.finish();
+}
+
+// the search the game plays with, from an empty table every time
+// throughput is the number of searched nodes, so criterion reports nodes/second
+fn bench_search(c: &mut Criterion) {
+    let mut searcher = Searcher::new(EvalParams::default());
+    let mut group = c.benchmark_group("search");
+    group.sample_size(10);
+
+    for depth in [4u8, 6, 8] {
+        for (name, board) in positions() {
+            searcher.clear();
+            let nodes = searcher.search_for(&board, Piece::AI, depth, None, None).nodes;
+            group.throughput(Throughput::Elements(nodes));
+            group.bench_with_input(BenchmarkId::new(format!("depth{}", depth), &name), &board, |b, board| {
+                b.iter(|| {
+                    searcher.clear();
+                    searcher.search_for(black_box(board), Piece::AI, depth, None, None)
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_drop_piece, bench_check_win, bench_evaluate, bench_search);
+criterion_main!(benches);
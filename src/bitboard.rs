@@ -1,34 +1,249 @@
 use std::fmt;
 
+use crate::rng::Rng;
+use crate::variant::Variant;
 
 #[derive(Clone, Debug)]
 pub struct BitBoard {
     pub player_mask: u128,  // bitboard for player pieces
     pub ai_mask: u128,      // bitboard for AI pieces
+    pub third_mask: u128,   // pieces of the third and fourth colour (games with more players)
+    pub fourth_mask: u128,
+    pub blocked_mask: u128, // neutral cells placed before the game - no piece can go there and they break lines
     heights: Vec<u8>,
+    valid_mask: u128,       // bits of all cells on the board (without the padding bits)
     pub rows: u8,
     pub cols: u8,
     pub connect: u8,        // number of pieces to connect to win
+    pub players: u8,        // number of colours taking turns (2-4)
+    pub variant: Variant,   // rules of the game
+    pub misere: bool,       // completing a line loses instead of winning
+    pub first: Piece,       // colour that made the first move, for the row parity of threats
+    kept: [u8; 2],          // Pop 10: pieces kept by the player and the AI
+    filled: bool,           // Pop 10: the board was filled, pops only from now on
 }
 
-#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
 pub enum Piece {
     Empty,
     Player,
     AI,
+    Third,      // AI colours in games with 3 or 4 players
+    Fourth,
+}
+
+// a position together with the rule state the masks do not show - identifies the position exactly
+pub type PositionKey = ([u128; 4], u16);
+
+impl Piece {
+    // colours in turn order
+    pub const COLOURS: [Piece; 4] = [Piece::Player, Piece::AI, Piece::Third, Piece::Fourth];
+
+    // the other side in a two player game
+    pub fn opponent(self) -> Piece {
+        match self {
+            Piece::Player => Piece::AI,
+            Piece::AI => Piece::Player,
+            _ => Piece::Empty,
+        }
+    }
+
+    // position in the turn order
+    pub fn index(self) -> usize {
+        match self {
+            Piece::Player | Piece::Empty => 0,
+            Piece::AI => 1,
+            Piece::Third => 2,
+            Piece::Fourth => 3,
+        }
+    }
+}
+
+impl fmt::Display for Piece {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Piece::Empty => "Nobody",
+            Piece::Player => "Player",
+            Piece::AI => "AI",
+            Piece::Third => "AI 2",
+            Piece::Fourth => "AI 3",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+pub enum Move {
+    Drop(u8),   // drop a piece into the column
+    Pop(u8),    // remove own piece from the bottom of the column (Pop Out)
+}
+
+impl Move {
+    pub fn col(self) -> u8 {
+        match self {
+            Move::Drop(col) | Move::Pop(col) => col,
+        }
+    }
+}
+
+// drops are written as the column number, pops with a "p" in front ("3", "p3")
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Move::Drop(col) => write!(f, "{}", col),
+            Move::Pop(col) => write!(f, "p{}", col),
+        }
+    }
+}
+
+impl std::str::FromStr for Move {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (pop, number) = match s.strip_prefix(['p', 'P']) {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let col = number.parse().map_err(|_| format!("Invalid move: {}", s))?;
+
+        Ok(if pop { Move::Pop(col) } else { Move::Drop(col) })
+    }
 }
 
 impl BitBoard {
     // create a new BitBoard with the given number of rows and columns
     pub fn new(rows: u8, cols: u8, connect: u8) -> Self {
+        let column = (1u128 << rows) - 1;
+        let valid_mask = (0..cols).fold(0, |mask, c| mask | column << (c as u32 * (rows as u32 + 1)));
+
         Self {
             player_mask: 0, // initialize empty boards
             ai_mask: 0,
+            third_mask: 0,
+            fourth_mask: 0,
+            blocked_mask: 0,
             heights: vec![0; cols as usize],
+            valid_mask,
             rows,
             cols,
-            connect
+            connect,
+            players: 2,
+            variant: Variant::Classic,
+            misere: false,
+            first: Piece::Player,
+            kept: [0, 0],
+            filled: false,
+        }
+    }
+
+    // sets the rules of the game
+    pub fn with_variant(mut self, variant: Variant) -> Self {
+        self.variant = variant;
+        self.misere &= variant.lines_win();
+        let players = self.players;
+        // pops move the pieces of a column down, blocked cells cannot stay in place
+        if variant.has_pops() {
+            self = self.with_blockers(&[]);
+        }
+        self.with_players(players)
+    }
+
+    // blocks the given (row, col) cells - cells off the board or with a piece are left out
+    // replaces any blocked cells from before, ignored in variants with pops
+    pub fn with_blockers(mut self, cells: &[(u8, u8)]) -> Self {
+        self.blocked_mask = 0;
+        if !self.variant.has_pops() {
+            for &(row, col) in cells.iter().filter(|&&(row, col)| row < self.rows && col < self.cols) {
+                self.blocked_mask |= self.bit(row, col);
+            }
+            self.blocked_mask &= !self.occupied();
+        }
+        for col in 0..self.cols {
+            self.update_height(col);
         }
+        self
+    }
+
+    // blocks `count` more empty cells picked at random - the same seed always blocks the same cells
+    pub fn with_random_blockers(self, count: u8, seed: u64) -> Self {
+        let mut free: Vec<(u8, u8)> = (0..self.cols)
+            .flat_map(|col| (0..self.rows).map(move |row| (row, col)))
+            .filter(|&(row, col)| self.get_piece(row, col) == Piece::Empty && !self.is_blocked(row, col))
+            .collect();
+        let mut cells = self.blocked_cells();
+
+        // partial Fisher-Yates shuffle, the first `count` cells are the picked ones
+        let mut rng = Rng::new(seed);
+        for i in 0..(count as usize).min(free.len()) {
+            let j = i + rng.below(free.len() - i);
+            free.swap(i, j);
+            cells.push(free[i]);
+        }
+
+        self.with_blockers(&cells)
+    }
+
+    // the blocked cells as (row, col)
+    pub fn blocked_cells(&self) -> Vec<(u8, u8)> {
+        (0..self.cols)
+            .flat_map(|col| (0..self.rows).map(move |row| (row, col)))
+            .filter(|&(row, col)| self.is_blocked(row, col))
+            .collect()
+    }
+
+    pub fn is_blocked(&self, row: u8, col: u8) -> bool {
+        self.blocked_mask & self.bit(row, col) != 0
+    }
+
+    // the colour that starts the game - it cannot be read from the pieces once pops take some away
+    pub fn with_first(mut self, first: Piece) -> Self {
+        self.first = first;
+        self
+    }
+
+    // misère rules: whoever completes a line loses (only for two players and variants where lines end the game)
+    pub fn with_misere(mut self, misere: bool) -> Self {
+        self.misere = misere && self.variant.lines_win() && self.players == 2;
+        self
+    }
+
+    // number of colours taking turns, limited to what the variant allows
+    pub fn with_players(mut self, players: u8) -> Self {
+        let allowed = self.variant.players();
+        self.players = players.clamp(*allowed.start(), *allowed.end());
+        self.misere &= self.players == 2;
+        self
+    }
+
+    // the colours playing this game, in turn order
+    pub fn colours(&self) -> &'static [Piece] {
+        &Piece::COLOURS[..self.players as usize]
+    }
+
+    // the colour that plays after `piece`
+    pub fn next_player(&self, piece: Piece) -> Piece {
+        Piece::COLOURS[(piece.index() + 1) % self.players as usize]
+    }
+
+    // the colour that played before `piece`
+    pub fn previous_player(&self, piece: Piece) -> Piece {
+        let players = self.players as usize;
+        Piece::COLOURS[(piece.index() + players - 1) % players]
+    }
+
+    // true for the same colour, or teammates when the variant plays in teams (first and third colour against
+    // second and fourth)
+    pub fn same_team(&self, a: Piece, b: Piece) -> bool {
+        match self.variant.has_teams() {
+            true => a.index() % 2 == b.index() % 2,
+            false => a == b,
+        }
+    }
+
+    // largest number of columns with the given rows that fits into the masks (with the padding bits)
+    pub fn max_cols(rows: u8) -> u8 {
+        128 / (rows + 1) - 1
     }
 
     // returns integer [0, rows * cols) where the bit is located in the bitboard (which bit in the u128 is this cell at)
@@ -47,6 +262,16 @@ impl BitBoard {
         self.heights[col as usize] < self.rows
     }
 
+    // returns the mask of all cells on the board
+    pub fn valid_cells(&self) -> u128 {
+        self.valid_mask
+    }
+
+    // returns the row where the next piece in the column lands (above the top piece or blocked cell)
+    pub fn height(&self, col: u8) -> u8 {
+        self.heights[col as usize]
+    }
+
     // returns a vector of all valid columns (where piece can be dropped)
     pub fn get_valid_locations(&self) -> Vec<u8> {
         (0..self.cols).filter(|&c| self.is_valid_location(c)).collect()
@@ -65,19 +290,156 @@ impl BitBoard {
         let bit_to_set = self.bit(row, col);
 
         let mut new_board = self.clone();
-        // increase the height of the column - piece is dropped
-        new_board.heights[col as usize] += 1;         
 
         // set the bit in the correct BitBoard (according to the player)
         match piece {
             Piece::Player => new_board.player_mask |= bit_to_set,
             Piece::AI => new_board.ai_mask |= bit_to_set,
-            _ => {}
+            Piece::Third => new_board.third_mask |= bit_to_set,
+            Piece::Fourth => new_board.fourth_mask |= bit_to_set,
+            Piece::Empty => {}
+        }
+        // increase the height of the column - piece is dropped (the next one lands above any blocked cells)
+        new_board.heights[col as usize] = row + 1;
+        new_board.update_height(col);
+
+        if self.variant == Variant::Pop10 && new_board.is_full() {
+            new_board.filled = true;
+        }
+
+        Some(new_board)
+    }
+
+    // moves the height of the column above its top piece or blocked cell - a blocked cell holds up the
+    // pieces like any other, the free cells under a floating one are never reached
+    fn update_height(&mut self, col: u8) {
+        let column = ((1u128 << self.rows) - 1) << self.bit_index(0, col);
+        let filled = ((self.occupied() | self.blocked_mask) & column) >> self.bit_index(0, col);
+        self.heights[col as usize] = (128 - filled.leading_zeros()) as u8;
+    }
+
+    // checks if the piece can be popped from the column - its own piece must be at the bottom
+    // (Pop Out any time, Pop 10 once the board was filled)
+    pub fn can_pop(&self, col: u8, piece: Piece) -> bool {
+        let allowed = match self.variant {
+            Variant::PopOut => true,
+            Variant::Pop10 => self.filled,
+            _ => false,
+        };
+        allowed && col < self.cols && self.get_piece(0, col) == piece && piece != Piece::Empty
+    }
+
+    // returns a new BitBoard with the bottom piece of the column removed and the column shifted down
+    // in Pop 10 the piece is kept if it was part of an own line, otherwise it goes back on top of the column
+    pub fn pop_piece(&self, col: u8, piece: Piece) -> Option<Self> {
+        if !self.can_pop(col, piece) {
+            return None;
+        }
+
+        let column = ((1u128 << self.rows) - 1) << self.bit_index(0, col);
+        let shift_down = |mask: u128| (mask & !column) | ((mask & column) >> 1 & column);
+
+        let mut new_board = self.clone();
+        new_board.player_mask = shift_down(self.player_mask);
+        new_board.ai_mask = shift_down(self.ai_mask);
+        new_board.third_mask = shift_down(self.third_mask);
+        new_board.fourth_mask = shift_down(self.fourth_mask);
+        new_board.heights[col as usize] -= 1;
+
+        if self.variant == Variant::Pop10 {
+            if self.line_cells(piece) & self.bit(0, col) != 0 {
+                new_board.kept[kept_index(piece)] += 1;
+            } else {
+                new_board = new_board.drop_piece(col, piece).expect("the popped column has room");
+            }
         }
 
         Some(new_board)
     }
 
+    // all moves the piece can make: drops into non-full columns, then pops (if the variant has them)
+    pub fn legal_moves(&self, piece: Piece) -> Vec<Move> {
+        let mut moves: Vec<Move> = match self.drops_allowed() {
+            true => self.get_valid_locations().into_iter().map(Move::Drop).collect(),
+            false => Vec::new(),
+        };
+        moves.extend((0..self.cols).filter(|&c| self.can_pop(c, piece)).map(Move::Pop));
+        moves
+    }
+
+    // false when the piece has nothing to play (a draw) - for the classic rules when the board is full
+    pub fn has_legal_move(&self, piece: Piece) -> bool {
+        (self.drops_allowed() && !self.is_full()) || (0..self.cols).any(|c| self.can_pop(c, piece))
+    }
+
+    // Pop 10 has no drops after the board was filled
+    fn drops_allowed(&self) -> bool {
+        !(self.variant == Variant::Pop10 && self.filled)
+    }
+
+    // true in Pop 10 once the board was filled (only pops are played from then on)
+    pub fn pop_phase(&self) -> bool {
+        self.filled
+    }
+
+    // pieces kept in Pop 10
+    pub fn kept(&self, piece: Piece) -> u8 {
+        match piece {
+            Piece::Player | Piece::AI => self.kept[kept_index(piece)],
+            _ => 0,
+        }
+    }
+
+    // returns a new BitBoard with the move played, None if the move is not legal
+    pub fn play(&self, mv: Move, piece: Piece) -> Option<Self> {
+        match mv {
+            Move::Drop(col) if col < self.cols && self.drops_allowed() => self.drop_piece(col, piece),
+            Move::Drop(_) => None,
+            Move::Pop(col) => self.pop_piece(col, piece),
+        }
+    }
+
+    // identifies the position together with the state the masks do not show (Pop 10 kept pieces and phase)
+    pub fn position_key(&self) -> PositionKey {
+        let state = self.kept[0] as u16 | (self.kept[1] as u16) << 7 | (self.filled as u16) << 14;
+        ([self.player_mask, self.ai_mask, self.third_mask, self.fourth_mask], state)
+    }
+
+    // the colour that won after `last_mover` made a move, if any (with teams the whole team of it wins)
+    // a pop can complete lines for both sides at once - then the player who popped wins (loses in misère)
+    pub fn winner(&self, last_mover: Piece) -> Option<Piece> {
+        if let Some(target) = self.variant.pieces_to_win() {
+            return self.turn_order(last_mover).find(|&p| self.kept(p) >= target);
+        }
+
+        let line = self.line_maker(last_mover);
+
+        match self.misere {
+            true => line.map(Piece::opponent),
+            false => line,
+        }
+    }
+
+    // the cells of the line that decided winner(last_mover) - in misère the line of the loser, empty when
+    // the game was not decided by a line
+    pub fn decisive_line(&self, last_mover: Piece) -> Vec<(u8, u8)> {
+        match (self.variant.pieces_to_win(), self.line_maker(last_mover)) {
+            (None, Some(piece)) => self.winning_line(piece),
+            _ => Vec::new(),
+        }
+    }
+
+    // the colour with a line - a pop can make lines for several, then it counts for the last mover
+    fn line_maker(&self, last_mover: Piece) -> Option<Piece> {
+        self.turn_order(last_mover).find(|&p| self.check_win(p))
+    }
+
+    // the last mover first, then the others in turn order
+    fn turn_order(&self, last_mover: Piece) -> impl Iterator<Item = Piece> {
+        let players = self.players as usize;
+        (0..players).map(move |i| Piece::COLOURS[(last_mover.index() + i) % players])
+    }
+
     // returns the piece at given row and column
     pub fn get_piece(&self, row: u8, col: u8) -> Piece {
         let bit = self.bit(row, col);
@@ -86,6 +448,10 @@ impl BitBoard {
             Piece::Player
         } else if self.ai_mask & bit != 0 {
             Piece::AI
+        } else if self.third_mask & bit != 0 {
+            Piece::Third
+        } else if self.fourth_mask & bit != 0 {
+            Piece::Fourth
         } else {
             Piece::Empty
         }
@@ -96,32 +462,107 @@ impl BitBoard {
         self.heights.iter().all(|&h| h >= self.rows)
     }
 
-    // checks if the given piece has won - using a bitwise shift method
-    pub fn check_win(&self, piece: Piece) -> bool {
-        // select the corresponding bitmask
-        let mask = match piece {
+    // returns the same position with the colours exchanged (player pieces become AI pieces and the other way)
+    // lets the AI search for the player side, e.g. in self-play
+    pub fn swapped(&self) -> Self {
+        let mut board = self.clone();
+        std::mem::swap(&mut board.player_mask, &mut board.ai_mask);
+        board.kept.swap(0, 1);
+        board.first = board.first.opponent();
+        board
+    }
+
+    // the pieces of one colour
+    pub fn mask(&self, piece: Piece) -> u128 {
+        match piece {
             Piece::Player => self.player_mask,
             Piece::AI => self.ai_mask,
-            _ => 0,
-        };
+            Piece::Third => self.third_mask,
+            Piece::Fourth => self.fourth_mask,
+            Piece::Empty => 0,
+        }
+    }
+
+    // all pieces on the board
+    pub fn occupied(&self) -> u128 {
+        self.player_mask | self.ai_mask | self.third_mask | self.fourth_mask
+    }
 
+    fn line_directions(&self) -> [u8; 4] {
         let row_stride = self.rows + 1; // 1 bit gap
-        let directions = [
+        [
             1,          // vertical
             row_stride,       // horizontal (moving to next column)
             row_stride - 1,   // diagonal / (one up, one right)
             row_stride + 1    // diagonal \ (one down, one right)
-        ];
-        
+        ]
+    }
+
+    // moves every cell of the mask one step along a line direction - towards higher bits if `up`
+    // cells leaving the board end in padding bits or past the u128, except on a cylinder, where
+    // horizontal and diagonal steps out of one side come back on the other
+    pub fn shift(&self, mask: u128, dir: u32, up: bool) -> u128 {
+        let moved = match up {
+            true => mask.checked_shl(dir).unwrap_or(0),
+            false => mask >> dir,
+        };
+        if !self.variant.wraps() || dir == 1 {
+            return moved;
+        }
+
+        let column = (1u128 << self.rows) - 1;
+        let width = self.cols as u32 * (self.rows as u32 + 1);
+        let wrapped = match up {
+            true => (mask & column << self.bit_index(0, self.cols - 1)) >> (width - dir),
+            false => (mask & column) << (width - dir),
+        };
+        (moved | wrapped) & self.valid_mask
+    }
+
+    // returns the mask of all cells that are part of a line of `connect` pieces
+    pub fn line_cells(&self, piece: Piece) -> u128 {
+        let mask = self.mask(piece);
+        let mut cells = 0;
+
+        for dir in self.line_directions() {
+            // bits where a line starts
+            let mut starts = mask;
+            for _ in 0..self.connect - 1 {
+                starts &= self.shift(starts, dir as u32, false);
+            }
+            for _ in 0..self.connect {
+                cells |= starts;
+                starts = self.shift(starts, dir as u32, true);
+            }
+        }
+
+        cells
+    }
+
+    // the (row, col) cells of every line of `connect` pieces of the colour, column by column - empty
+    // without a line (misère: the cells of the losing line)
+    pub fn winning_line(&self, piece: Piece) -> Vec<(u8, u8)> {
+        let cells = self.line_cells(piece);
+        (0..self.cols)
+            .flat_map(|col| (0..self.rows).map(move |row| (row, col)))
+            .filter(|&(row, col)| cells & self.bit(row, col) != 0)
+            .collect()
+    }
+
+    // checks if the given piece has won - using a bitwise shift method
+    pub fn check_win(&self, piece: Piece) -> bool {
+        // select the corresponding bitmask
+        let mask = self.mask(piece);
+
         // check for each direction
-        for dir in directions {
+        for dir in self.line_directions() {
             let mut current = mask;
 
             // shift and AND the mask multiple times to detect a sequence - repeat for connect-1 times
             // example: connect 4 => 3 times shift and AND
             for _ in 0..self.connect - 1 {
                 // shift the mask in direction once
-                current = current & (current >> dir);
+                current = current & self.shift(current, dir as u32, false);
             }
 
             // if bits remain after shifting - won
@@ -140,9 +581,12 @@ impl fmt::Display for BitBoard {
         for r in (0..self.rows).rev() {
             for c in 0..self.cols {
                 let symbol = match self.get_piece(r, c) {
+                    Piece::Empty if self.is_blocked(r, c) => '#',
                     Piece::Empty => '.',
                     Piece::Player => 'X',
                     Piece::AI => 'O',
+                    Piece::Third => '+',
+                    Piece::Fourth => '*',
                 };
                 write!(f, " {} ", symbol)?;
             }
@@ -154,6 +598,17 @@ impl fmt::Display for BitBoard {
             write!(f, " {} ", c)?;
         }
         writeln!(f)?; // final newline
+
+        if let Some(target) = self.variant.pieces_to_win() {
+            writeln!(f, "Kept pieces: X {}, O {} (first to {})", self.kept(Piece::Player), self.kept(Piece::AI), target)?;
+        }
         Ok(())
     }
 }
+
+fn kept_index(piece: Piece) -> usize {
+    match piece {
+        Piece::AI => 1,
+        _ => 0,
+    }
+}
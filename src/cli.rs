@@ -0,0 +1,261 @@
+use std::env;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use std::time::Duration;
+
+use crate::bitboard::BitBoard;
+use crate::clock::TimeControl;
+use crate::minimax::TieBreak;
+use crate::net::NetRole;
+use crate::params::EvalParams;
+use crate::rng::random_seed;
+use crate::stats;
+use crate::tuner::TuneConfig;
+use crate::variant::Variant;
+
+pub const USAGE: &str = "\
+Usage: connect-4 [stats | replay [N]] [options]
+
+Commands:
+  stats               show the results of your games and your estimated rating
+  replay [N]          step through recorded game N (as numbered by stats, default the last one)
+
+Options:
+  --random-ties       pick randomly between equally good AI moves
+  --tie-margin <N>    treat moves within N points of the best as equal (implies --random-ties)
+  --seed <N>          seed for the random choices (implies --random-ties)
+  --ponder            let the AI think during your turn
+  --variant <NAME>    rules to play: classic, pop-out, pop-10, five-in-a-row, connect-4x4, cylinder (default classic)
+  --pop-out           same as --variant pop-out
+  --misere            misère rules: completing a line loses
+  --players <N>       number of colours, 2-4 (you against N-1 AIs, default 2)
+  --blockers <N|CELLS>  neutral blocked cells: a number of random cells, or fixed row:col cells
+                      (e.g. 0:3,2:5, cells off the board are left out) - not with pops
+  --blocker-seed <N>  seed for the random blocked cells
+  --clock <MIN[+SEC]>  play on a clock: minutes per player, plus seconds added after each move
+                      (e.g. 5 or 3+2) - running out of time loses
+  --move-limit <SEC>  the most a single move may take - going over loses
+  --host <PORT>       play against a person over the network, waiting for them on the port
+  --bind <ADDR>       the address --host listens on, e.g. 127.0.0.1 (default all of them)
+  --join <HOST:PORT>  join a game hosted with --host (the host picks the board and rules)
+  --engine            talk to another program over stdin/stdout with UCI-like commands
+  --server <ADDR>     answer POST /analyze and POST /move as JSON on ADDR, e.g. 127.0.0.1:8080
+                      (needs a build with --features server)
+  --stats-file <FILE>  where finished games are recorded (default ~/.connect-4-stats.toml,
+                      or the CONNECT4_STATS environment variable)
+  --eval <FILE>       load evaluation weights from a TOML file
+  --eval-param <K=V>  override one evaluation weight (can be repeated)
+  --dump-eval         print the evaluation weights as TOML and exit
+  --tune <FILE>       tune the evaluation weights by self-play and write them to FILE
+  --tune-iterations <N>  number of tuning iterations (default 100)
+  --tune-games <N>    game pairs played per iteration (default 8)
+  --tune-depth <N>    search depth used in self-play (default 4)
+  -h, --help          print this help";
+
+// options given on the command line - board settings are still asked interactively
+#[derive(Debug, Default, Clone)]
+pub struct Args {
+    pub random_ties: bool,
+    pub tie_margin: i32,
+    pub seed: Option<u64>,
+    pub ponder: bool,
+    pub variant: Variant,
+    pub misere: bool,
+    pub players: u8,
+    pub blocker_count: u8,
+    pub blocker_cells: Vec<(u8, u8)>,
+    pub blocker_seed: u64,
+    pub time_control: TimeControl,
+    pub network: Option<NetRole>,
+    pub bind: Option<IpAddr>,
+    pub engine: bool,
+    pub server: Option<String>,
+    pub stats: bool,
+    pub replay: Option<Option<usize>>, // the replay command, with the number of the game
+    pub stats_file: PathBuf,
+    pub eval_file: Option<PathBuf>,
+    pub eval_overrides: Vec<(String, String)>,
+    pub dump_eval: bool,
+    pub tune: Option<TuneConfig>,
+    pub help: bool,
+}
+
+impl Args {
+    pub fn parse() -> Result<Self, String> {
+        Self::parse_from(env::args().skip(1))
+    }
+
+    pub fn parse_from<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter().peekable();
+        let mut seed_given = None;
+        let mut stats_file = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--random-ties" => parsed.random_ties = true,
+                "--tie-margin" => {
+                    parsed.tie_margin = parse_value(&arg, args.next())?;
+                    if parsed.tie_margin < 0 {
+                        return Err("--tie-margin must not be negative".to_string());
+                    }
+                    parsed.random_ties = true;
+                }
+                "--seed" => {
+                    parsed.seed = Some(parse_value(&arg, args.next())?);
+                    parsed.random_ties = true;
+                }
+                "--ponder" => parsed.ponder = true,
+                "--variant" => {
+                    let name: String = parse_value(&arg, args.next())?;
+                    parsed.variant = name.parse()?;
+                }
+                "--pop-out" => parsed.variant = Variant::PopOut,
+                "--misere" => parsed.misere = true,
+                "--players" => parsed.players = parse_value(&arg, args.next())?,
+                "--blockers" => {
+                    let value: String = parse_value(&arg, args.next())?;
+                    match value.parse() {
+                        Ok(count) => parsed.blocker_count = count,
+                        Err(_) => parsed.blocker_cells = parse_cells(&value)?,
+                    }
+                }
+                "--clock" => {
+                    let value: String = parse_value(&arg, args.next())?;
+                    let per_move = parsed.time_control.per_move;
+                    parsed.time_control = TimeControl { per_move, ..value.parse()? };
+                }
+                "--move-limit" => {
+                    let seconds: f64 = parse_value(&arg, args.next())?;
+                    if !(seconds > 0.0 && seconds.is_finite()) {
+                        return Err("--move-limit must be a positive number of seconds".to_string());
+                    }
+                    parsed.time_control.per_move = Some(Duration::from_secs_f64(seconds));
+                }
+                "--host" => parsed.network = Some(NetRole::Host(parse_value(&arg, args.next())?)),
+                "--bind" => parsed.bind = Some(parse_value(&arg, args.next())?),
+                "--join" => parsed.network = Some(NetRole::Join(parse_value(&arg, args.next())?)),
+                "--engine" => parsed.engine = true,
+                "--server" => parsed.server = Some(parse_value(&arg, args.next())?),
+                "stats" => parsed.stats = true,
+                "replay" => parsed.replay = Some(args.next_if(|next| next.parse::<usize>().is_ok()).and_then(|n| n.parse().ok())),
+                "--stats-file" => stats_file = Some(PathBuf::from(parse_value::<String>(&arg, args.next())?)),
+                "--blocker-seed" => seed_given = Some(parse_value(&arg, args.next())?),
+                "--eval" => {
+                    let path: String = parse_value(&arg, args.next())?;
+                    parsed.eval_file = Some(PathBuf::from(path));
+                }
+                "--eval-param" => {
+                    let pair: String = parse_value(&arg, args.next())?;
+                    let (key, value) = pair.split_once('=')
+                        .ok_or_else(|| format!("Expected KEY=VALUE for {}, got {}", arg, pair))?;
+                    parsed.eval_overrides.push((key.trim().to_string(), value.trim().to_string()));
+                }
+                "--dump-eval" => parsed.dump_eval = true,
+                "--tune" => {
+                    let path: String = parse_value(&arg, args.next())?;
+                    parsed.tune.get_or_insert_with(TuneConfig::default).output = PathBuf::from(path);
+                }
+                "--tune-iterations" => {
+                    parsed.tune.get_or_insert_with(TuneConfig::default).iterations = parse_value(&arg, args.next())?;
+                }
+                "--tune-games" => {
+                    parsed.tune.get_or_insert_with(TuneConfig::default).games = parse_value(&arg, args.next())?;
+                }
+                "--tune-depth" => {
+                    parsed.tune.get_or_insert_with(TuneConfig::default).depth = parse_value(&arg, args.next())?;
+                }
+                "-h" | "--help" => parsed.help = true,
+                _ => return Err(format!("Unknown option: {}", arg)),
+            }
+        }
+
+        if parsed.misere && !parsed.variant.lines_win() {
+            return Err(format!("--misere cannot be used with {} - lines do not end the game", parsed.variant));
+        }
+
+        let allowed = parsed.variant.players();
+        if parsed.players == 0 {
+            parsed.players = *allowed.start();
+        } else if !allowed.contains(&parsed.players) {
+            return Err(format!("{} is played by {} to {} players", parsed.variant, allowed.start(), allowed.end()));
+        }
+        if parsed.misere && parsed.players > 2 {
+            return Err("--misere needs two players".to_string());
+        }
+
+        if (parsed.blocker_count > 0 || !parsed.blocker_cells.is_empty()) && parsed.variant.has_pops() {
+            return Err(format!("--blockers cannot be used with {} - pops move the pieces of a column", parsed.variant));
+        }
+        parsed.blocker_seed = seed_given.unwrap_or_else(random_seed);
+        parsed.stats_file = stats_file.unwrap_or_else(stats::default_path);
+
+        if parsed.network.is_some() && parsed.players != 2 {
+            return Err("Network games are played by two".to_string());
+        }
+        if parsed.network.is_some() && parsed.time_control.is_timed() {
+            return Err("Network games have no clock".to_string());
+        }
+        if parsed.bind.is_some() && !matches!(parsed.network, Some(NetRole::Host(_))) {
+            return Err("--bind needs --host".to_string());
+        }
+
+        if let Some(tune) = &mut parsed.tune {
+            if tune.iterations == 0 || tune.games == 0 || tune.depth == 0 {
+                return Err("Tuning iterations, games and depth must be at least 1".to_string());
+            }
+            tune.seed = parsed.seed.unwrap_or_else(random_seed);
+        }
+
+        Ok(parsed)
+    }
+
+    // evaluation weights: defaults, then the file, then single overrides
+    pub fn eval_params(&self) -> Result<EvalParams, String> {
+        let mut params = match &self.eval_file {
+            Some(path) => EvalParams::load(path)?,
+            None => EvalParams::default(),
+        };
+
+        for (key, value) in &self.eval_overrides {
+            params.set(key, value)?;
+        }
+
+        Ok(params)
+    }
+
+    // the fixed blocked cells, then the random ones on top
+    pub fn place_blockers(&self, board: BitBoard) -> BitBoard {
+        board.with_blockers(&self.blocker_cells).with_random_blockers(self.blocker_count, self.blocker_seed)
+    }
+
+    // returns the tie breaker for the AI (if enabled) - the seed is printed so the game can be replayed
+    pub fn tie_break(&self) -> Option<TieBreak> {
+        if !self.random_ties {
+            return None;
+        }
+
+        let seed = self.seed.unwrap_or_else(random_seed);
+        println!("Random tie-breaking: seed {} (margin {}), rerun with --seed {} to reproduce", seed, self.tie_margin, seed);
+        Some(TieBreak::new(seed, self.tie_margin))
+    }
+}
+
+// "0:3, 2:5" - row:col pairs separated by commas
+fn parse_cells(value: &str) -> Result<Vec<(u8, u8)>, String> {
+    value.split(',')
+        .map(|cell| {
+            let (row, col) = cell.split_once(':')
+                .ok_or_else(|| format!("Expected a number or row:col cells for --blockers, got {}", cell.trim()))?;
+            let row = parse_value("--blockers", Some(row.to_string()))?;
+            let col = parse_value("--blockers", Some(col.to_string()))?;
+            Ok((row, col))
+        })
+        .collect()
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("Missing value for {}", flag))?;
+    value.trim().parse().map_err(|_| format!("Invalid value for {}: {}", flag, value))
+}
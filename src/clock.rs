@@ -0,0 +1,195 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use web_time::Instant;
+
+use crate::bitboard::{BitBoard, Piece};
+use crate::stats::GameResult;
+
+// the time manager plans for at least this many moves of its own, so it never bets the whole clock on one
+const MIN_MOVES_LEFT: u32 = 5;
+// kept back from every move for what happens around the search (the search also overshoots a little)
+const SAFETY_MARGIN: Duration = Duration::from_millis(50);
+
+// timing of a game: a base time per player (sudden death), seconds added after each move (increment)
+// and a limit on a single move - each part is optional
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TimeControl {
+    pub base: Option<Duration>,
+    pub increment: Duration,
+    pub per_move: Option<Duration>,
+}
+
+impl TimeControl {
+    pub fn is_timed(&self) -> bool {
+        self.base.is_some() || self.per_move.is_some()
+    }
+}
+
+// "5" is five minutes sudden death, "5+3" adds three seconds after each move, "0.5+1" is thirty seconds
+impl FromStr for TimeControl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (minutes, increment) = s.split_once('+').unwrap_or((s, "0"));
+        let minutes: f64 = minutes.trim().parse().ok().filter(|m: &f64| *m > 0.0 && m.is_finite())
+            .ok_or_else(|| format!("Expected minutes or minutes+seconds for the clock, got {}", s))?;
+        let increment: f64 = increment.trim().parse().ok().filter(|i: &f64| *i >= 0.0 && i.is_finite())
+            .ok_or_else(|| format!("Invalid increment: {}", increment))?;
+
+        Ok(Self {
+            base: Some(Duration::from_secs_f64(minutes * 60.0)),
+            increment: Duration::from_secs_f64(increment),
+            per_move: None,
+        })
+    }
+}
+
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(base) = self.base {
+            parts.push(format!("{} min", base.as_secs_f64() / 60.0));
+        }
+        if !self.increment.is_zero() {
+            parts.push(format!("+{} s per move", self.increment.as_secs_f64()));
+        }
+        if let Some(limit) = self.per_move {
+            parts.push(format!("at most {} s a move", limit.as_secs_f64()));
+        }
+        match parts.is_empty() {
+            true => write!(f, "untimed"),
+            false => write!(f, "{}", parts.join(", ")),
+        }
+    }
+}
+
+// the clocks of all colours - only the colour to move runs
+#[derive(Debug, Clone)]
+pub struct Clock {
+    pub control: TimeControl,
+    remaining: [Duration; 4],
+    running: Option<(Piece, Instant)>,
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Self {
+        Self {
+            control,
+            remaining: [control.base.unwrap_or(Duration::MAX); 4],
+            running: None,
+        }
+    }
+
+    // starts the move of `piece` - a colour that is already on the move keeps its clock running
+    pub fn start(&mut self, piece: Piece) {
+        match self.running {
+            Some((running, _)) if running == piece => {}
+            _ => self.running = Some((piece, Instant::now())),
+        }
+    }
+
+    // ends the move of the running colour - returns false when its flag fell first, the increment is
+    // only added to a move in time
+    pub fn stop(&mut self) -> bool {
+        let Some((piece, started)) = self.running.take() else {
+            return true;
+        };
+        let used = started.elapsed();
+        let in_time = used <= self.move_time(piece, Duration::ZERO);
+
+        let remaining = &mut self.remaining[piece.index()];
+        *remaining = remaining.saturating_sub(used);
+        if in_time && self.control.base.is_some() {
+            *remaining += self.control.increment;
+        }
+        in_time
+    }
+
+    // the colour on the move whose time is up
+    pub fn flagged(&self) -> Option<Piece> {
+        let (piece, started) = self.running?;
+        (started.elapsed() > self.move_time(piece, Duration::ZERO)).then_some(piece)
+    }
+
+    pub fn running(&self) -> Option<Piece> {
+        self.running.map(|(piece, _)| piece)
+    }
+
+    // time left on the clock of `piece`, counting the move in progress (None without a base time)
+    pub fn remaining(&self, piece: Piece) -> Option<Duration> {
+        self.control.base?;
+        let remaining = self.remaining[piece.index()];
+        Some(match self.running {
+            Some((running, started)) if running == piece => remaining.saturating_sub(started.elapsed()),
+            _ => remaining,
+        })
+    }
+
+    // time `piece` may still use for the move in progress, the clock or the move limit, whichever ends first
+    pub fn time_for_move(&self, piece: Piece) -> Option<Duration> {
+        if !self.control.is_timed() {
+            return None;
+        }
+        let used = match self.running {
+            Some((running, started)) if running == piece => started.elapsed(),
+            _ => Duration::ZERO,
+        };
+        Some(self.move_time(piece, Duration::ZERO).saturating_sub(used))
+    }
+
+    // time manager: the thinking time for the next AI move - an even share of the clock over the moves that
+    // are likely left, plus most of the increment, and never more than the move limit allows
+    pub fn think_time(&self, board: &BitBoard, piece: Piece) -> Option<Duration> {
+        if !self.control.is_timed() {
+            return None;
+        }
+        let free = (board.rows as u32 * board.cols as u32).saturating_sub((board.occupied() | board.blocked_mask).count_ones());
+        let moves_left = (free / board.players as u32).max(MIN_MOVES_LEFT);
+
+        let share = match self.control.base {
+            Some(_) => self.remaining[piece.index()] / moves_left + self.control.increment * 3 / 4,
+            None => Duration::MAX,
+        };
+        let available = self.move_time(piece, SAFETY_MARGIN);
+        Some(share.min(available))
+    }
+
+    // the longest a move of `piece` can take, less `margin` of each limit
+    fn move_time(&self, piece: Piece, margin: Duration) -> Duration {
+        let clock = match self.control.base {
+            Some(_) => self.remaining[piece.index()].saturating_sub(margin),
+            None => Duration::MAX,
+        };
+        let limit = self.control.per_move.map_or(Duration::MAX, |limit| limit.saturating_sub(margin));
+        clock.min(limit)
+    }
+}
+
+// the end of a game on time - with more players there is no single winner, the colour just loses
+// (with teams, its whole team)
+pub fn flag_message(board: &BitBoard, flagged: Piece) -> String {
+    match (board.players, board.variant.has_teams()) {
+        (_, true) if board.same_team(flagged, Piece::Player) => format!("{} ran out of time - the other team wins!", flagged),
+        (_, true) => format!("{} ran out of time - your team wins!", flagged),
+        (2, _) => format!("{} ran out of time - {} wins!", flagged, flagged.opponent()),
+        _ => format!("{} ran out of time and loses!", flagged),
+    }
+}
+
+// the result for the human when the flag of `flagged` falls - the flag of a teammate loses too
+pub fn flag_result(board: &BitBoard, flagged: Piece) -> GameResult {
+    match board.same_team(flagged, Piece::Player) {
+        true => GameResult::Loss,
+        false => GameResult::Win,
+    }
+}
+
+// 4:05 for minutes, 9.3 under ten seconds
+pub fn format_time(time: Duration) -> String {
+    match time.as_secs() {
+        secs if secs < 10 => format!("{:.1}", time.as_secs_f64()),
+        secs => format!("{}:{:02}", secs / 60, secs % 60),
+    }
+}
@@ -0,0 +1,166 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use web_time::Instant;
+
+use crate::bitboard::{BitBoard, Piece};
+use crate::minimax::TieBreak;
+use crate::search::{SearchInfo, Searcher};
+
+// thinking time of one step of a stepped search - short enough to keep the frames coming
+const STEP_TIME: Duration = Duration::from_millis(15);
+
+// background search during the human's turn
+// the searcher is moved to the thread and handed back (with the filled table) when the human has moved
+struct Ponder {
+    handle: JoinHandle<(Searcher, u8)>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Ponder {
+    // `board` is the position with the human to move, `depth` the search depth of the AI reply
+    fn start(mut searcher: Searcher, board: BitBoard, depth: u8) -> Self {
+        let stop = searcher.stop_flag();
+        stop.store(false, Ordering::Relaxed);
+
+        let handle = thread::spawn(move || {
+            // one ply deeper: the human move plus the full depth of the AI reply
+            let pondered = searcher.ponder(&board, depth.saturating_add(1));
+            (searcher, pondered)
+        });
+
+        Self { handle, stop }
+    }
+
+    // stops the background search, returns the searcher and the depth that was finished
+    fn stop(self) -> (Searcher, u8) {
+        self.stop.store(true, Ordering::Relaxed);
+        let (searcher, pondered) = self.handle.join().expect("ponder thread panicked");
+        self.stop.store(false, Ordering::Relaxed);
+        (searcher, pondered)
+    }
+}
+
+// owns the searcher of a game, optionally pondering on the human's time
+pub struct Engine {
+    searcher: Option<Searcher>,
+    ponder: Option<Ponder>,
+    pub pondering_enabled: bool,
+    pub last_ponder_depth: u8,  // depth finished during the last human turn
+}
+
+impl Engine {
+    pub fn new(searcher: Searcher, pondering_enabled: bool) -> Self {
+        Self {
+            searcher: Some(searcher),
+            ponder: None,
+            // the browser has no threads to ponder in
+            pondering_enabled: pondering_enabled && cfg!(not(target_arch = "wasm32")),
+            last_ponder_depth: 0,
+        }
+    }
+
+    // starts the background search if pondering is enabled (`board` has the human to move)
+    pub fn start_pondering(&mut self, board: &BitBoard, depth: u8) {
+        if !self.pondering_enabled || self.ponder.is_some() {
+            return;
+        }
+        if let Some(searcher) = self.searcher.take() {
+            self.ponder = Some(Ponder::start(searcher, board.clone(), depth));
+        }
+    }
+
+    pub fn stop_pondering(&mut self) {
+        if let Some(ponder) = self.ponder.take() {
+            let (searcher, depth) = ponder.stop();
+            self.searcher = Some(searcher);
+            self.last_ponder_depth = depth;
+        }
+    }
+
+    pub fn is_pondering(&self) -> bool {
+        self.ponder.is_some()
+    }
+
+    // the searcher for the AI move - pondering is stopped first
+    pub fn searcher(&mut self) -> &mut Searcher {
+        self.stop_pondering();
+        self.searcher.as_mut().expect("searcher is back after pondering")
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        self.stop_pondering();
+    }
+}
+
+// a search split into short steps, so that a caller without threads (the GUI in the browser) can draw
+// frames in between - every step goes on with the depth the last one was working on, and the subtrees it
+// finished are read back from the table
+pub struct SteppedSearch {
+    board: BitBoard,
+    me: Piece,
+    depth: u8,
+    next_depth: u8,
+    info: SearchInfo,     // the deepest finished depth so far
+    started: Instant,
+    time_limit: Option<Duration>, // thinking time of all steps together, counted from `new`
+}
+
+impl SteppedSearch {
+    pub fn new(board: &BitBoard, me: Piece, depth: u8) -> Self {
+        Self {
+            board: board.clone(),
+            me,
+            depth: depth.max(1),
+            next_depth: 1,
+            info: SearchInfo::default(),
+            started: Instant::now(),
+            time_limit: None,
+        }
+    }
+
+    pub fn with_time_limit(mut self, time_limit: Option<Duration>) -> Self {
+        self.time_limit = time_limit;
+        self
+    }
+
+    // searches for at most STEP_TIME, returns the result once the full depth is done
+    // the tie break and progress see every finished depth once, as in a plain search
+    // when the time limit runs out first, the deepest finished depth is played
+    pub fn step(
+        &mut self,
+        searcher: &mut Searcher,
+        mut tie_break: Option<&mut TieBreak>,
+        mut progress: Option<&mut dyn FnMut(&SearchInfo)>,
+    ) -> Option<SearchInfo> {
+        let end = self.time_limit.map(|limit| self.started + limit);
+        let deadline = end.map_or(Instant::now() + STEP_TIME, |end| end.min(Instant::now() + STEP_TIME));
+
+        loop {
+            let result = searcher.search_depth(&self.board, self.me, self.next_depth, &mut self.info, tie_break.as_deref_mut(), Some(deadline));
+            self.info.elapsed = self.started.elapsed();
+            let out_of_time = end.is_some_and(|end| Instant::now() >= end);
+
+            match result {
+                Some(last) => {
+                    if let Some(progress) = progress.as_mut() {
+                        progress(&self.info);
+                    }
+                    if last || self.next_depth >= self.depth || out_of_time {
+                        return Some(self.info.clone());
+                    }
+                    self.next_depth += 1;
+                    if Instant::now() >= deadline {
+                        return None;
+                    }
+                }
+                None if out_of_time => return Some(self.info.clone()),
+                None => return None,
+            }
+        }
+    }
+}
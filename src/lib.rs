@@ -0,0 +1,18 @@
+pub mod bitboard;
+pub mod cli;
+pub mod clock;
+pub mod engine;
+pub mod terminal;
+pub mod minimax;
+pub mod net;
+pub mod params;
+pub mod replay;
+pub mod rng;
+pub mod search;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod stats;
+pub mod tuner;
+pub mod uci;
+pub mod ui;
+pub mod variant;
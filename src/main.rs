@@ -1,13 +1,78 @@
-mod bitboard;
-mod terminal;
-mod minimax;
-mod ui;
-
-use ui::Connect4App;
+use connect_4::ui::Connect4App;
+use connect_4::cli::Args;
+#[cfg(not(target_arch = "wasm32"))]
+use connect_4::{tuner, uci};
+#[cfg(not(target_arch = "wasm32"))]
 use eframe::egui;
-use terminal::{game_mode_settings_input, main_loop_terminal, Mode};
+#[cfg(not(target_arch = "wasm32"))]
+use connect_4::cli::USAGE;
+#[cfg(not(target_arch = "wasm32"))]
+use connect_4::stats::Stats;
+#[cfg(not(target_arch = "wasm32"))]
+use connect_4::terminal::{game_mode_settings_input, main_loop_network, main_loop_terminal, replay_terminal, Mode};
 
+#[cfg(not(target_arch = "wasm32"))]
 fn main() -> eframe::Result<()> {
+    let args = match Args::parse() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if args.help {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+
+    let params = match args.eval_params() {
+        Ok(params) => params,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+
+    if args.dump_eval {
+        print!("{}", params.to_toml());
+        return Ok(());
+    }
+
+    if args.stats {
+        match Stats::load(&args.stats_file) {
+            Ok(stats) => println!("{}", stats.report()),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(config) = &args.tune {
+        if let Err(err) = tuner::tune(params, config) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(address) = &args.server {
+        run_server(address, params);
+        return Ok(());
+    }
+
+    if args.engine {
+        uci::run(std::io::stdin().lock(), std::io::stdout(), params);
+        return Ok(());
+    }
+
+    if let Some(role) = &args.network {
+        main_loop_network(&args, role);
+        return Ok(());
+    }
+
     let mode = game_mode_settings_input();
 
     if mode == Mode::Ui {
@@ -21,11 +86,52 @@ fn main() -> eframe::Result<()> {
             "Connect N",
             options,
             Box::new(|cc| {
-                Ok(Box::new(Connect4App::default(cc)))
+                Ok(Box::new(Connect4App::default(cc, &args, params)))
             }),
         )
+    } else if let Some(number) = args.replay {
+        replay_terminal(&args, &params, number);
+        Ok(())
     } else {
-        main_loop_terminal();
+        main_loop_terminal(&args, &params);
         Ok(())
     }
 }
+
+// in the browser there is no command line - the game starts on the settings screen
+#[cfg(target_arch = "wasm32")]
+fn main() {
+    use eframe::wasm_bindgen::JsCast;
+
+    let args = Args::parse_from(Vec::<String>::new()).expect("the defaults are valid");
+    let params = args.eval_params().expect("the default weights are valid");
+
+    wasm_bindgen_futures::spawn_local(async move {
+        let canvas = eframe::web_sys::window()
+            .and_then(|window| window.document())
+            .and_then(|document| document.get_element_by_id("connect4_canvas"))
+            .and_then(|element| element.dyn_into::<eframe::web_sys::HtmlCanvasElement>().ok())
+            .expect("index.html has a canvas with the id connect4_canvas");
+
+        let started = eframe::WebRunner::new()
+            .start(canvas, eframe::WebOptions::default(), Box::new(|cc| Ok(Box::new(Connect4App::default(cc, &args, params)))))
+            .await;
+        if let Err(err) = started {
+            eframe::web_sys::console::error_1(&err);
+        }
+    });
+}
+
+#[cfg(all(feature = "server", not(target_arch = "wasm32")))]
+fn run_server(address: &str, params: connect_4::params::EvalParams) {
+    if let Err(err) = connect_4::server::run(address, params) {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(all(not(feature = "server"), not(target_arch = "wasm32")))]
+fn run_server(_address: &str, _params: connect_4::params::EvalParams) {
+    eprintln!("This build has no server, build it with: cargo build --release --features server");
+    std::process::exit(2);
+}
@@ -1,40 +1,64 @@
-use std::i32;
-use std::cmp::{min, max};
 use std::collections::HashSet;
 
-use crate::bitboard::{BitBoard, Piece};
+use crate::bitboard::{BitBoard, Move, Piece};
+use crate::params::EvalParams;
+use crate::rng::Rng;
+use crate::search::Searcher;
 
+// score of a won position in the search (the heuristic stays far below)
+pub const WIN_SCORE: i32 = 100_000_000;
 
-fn evaluate_heuristic(board: &BitBoard, piece: Piece) -> i32 {
-    let win_score = 1_000_000;
-    let double_threat_bonus = 5000;
+// random choice between AI moves that score within `margin` of the best move
+pub struct TieBreak {
+    pub seed: u64,
+    pub margin: i32,
+    rng: Rng,
+}
+
+impl TieBreak {
+    pub fn new(seed: u64, margin: i32) -> Self {
+        Self {
+            seed,
+            margin,
+            rng: Rng::new(seed),
+        }
+    }
+
+    // random index in [0, n)
+    pub fn pick(&mut self, n: usize) -> usize {
+        self.rng.below(n)
+    }
+}
+
+// scores the position from the view of `piece`, `to_move` is the side that plays next
+pub fn evaluate_heuristic(board: &BitBoard, piece: Piece, to_move: Piece, params: &EvalParams) -> i32 {
+    let win_score = params.win_score;
+    let double_threat_bonus = params.double_threat_bonus;
 
-    let (player_mask, opponent_mask) = match piece {
-        Piece::AI => (board.ai_mask, board.player_mask),
-        Piece::Player => (board.player_mask, board.ai_mask),
-        _ => return 0,
-    };
+    if piece == Piece::Empty {
+        return 0;
+    }
 
-    if board.check_win(piece) {
-        return win_score;
+    // in Pop 10 lines only mean pieces to keep later, in misère a line loses
+    if board.variant.lines_win() {
+        let sign = if board.misere { -1 } else { 1 };
+        let others = board.colours().iter().copied().filter(|&c| c != piece);
+        if let Some(colour) = std::iter::once(piece).chain(others).find(|&c| board.check_win(c)) {
+            return if board.same_team(colour, piece) { sign * win_score } else { -sign * win_score };
+        }
     }
-    let opponent_piece = match piece {
-        Piece::AI => Piece::Player,
-        Piece::Player => Piece::AI,
-        _ => return 0,
-    };
-    if board.check_win(opponent_piece) {
-        return -win_score;
+
+    if board.players > 2 {
+        return evaluate_multiplayer(board, piece, to_move, params);
     }
 
-    let mut score = 0;
+    let opponent_piece = piece.opponent();
+    let (player_mask, opponent_mask) = (board.mask(piece), board.mask(opponent_piece));
+
+    let mut score = params.kept_piece * (board.kept(piece) as i32 - board.kept(opponent_piece) as i32);
 
     for len in (2..board.connect).rev() {
-        let weight = match len {
-            l if l == board.connect - 1 => 1000,
-            l if l == board.connect - 2 => 100,
-            _ => 10,
-        };
+        let weight = sequence_weight(board, len, params);
 
         let player_count = count_unblocked_sequences(board, player_mask, opponent_mask, len);
         let opponent_count = count_unblocked_sequences(board, opponent_mask, player_mask, len);
@@ -53,9 +77,140 @@ fn evaluate_heuristic(board: &BitBoard, piece: Piece) -> i32 {
         score -= double_threat_bonus;
     }
 
-    let center_score = center_preference(board, piece);
+    score += threat_parity_score(board, piece, to_move, &player_threats, &opponent_threats, params);
+
+    let center_score = params.center_weight * center_preference(board, piece);
+
+    // in misère own sequences, threats and central pieces are liabilities - the cells that complete
+    // lines cannot be played, and the side that runs out of other moves loses
+    match board.misere {
+        true => -(score + center_score),
+        false => score + center_score,
+    }
+}
+
+// paranoid view for 3 or 4 players: the colours of the own team against all others, and the lines of
+// every colour are blocked by all other pieces - there is no parity, the zugzwang at the end of the
+// game does not work out with more colours
+fn evaluate_multiplayer(board: &BitBoard, piece: Piece, to_move: Piece, params: &EvalParams) -> i32 {
+    let occupied = board.occupied();
+    let mut score = 0;
+
+    for &colour in board.colours() {
+        let sign = if board.same_team(colour, piece) { 1 } else { -1 };
+        let mine = board.mask(colour);
+        let others = occupied & !mine;
+
+        for len in (2..board.connect).rev() {
+            score += sign * sequence_weight(board, len, params) * count_unblocked_sequences(board, mine, others, len) as i32;
+        }
+
+        let threats = find_threat_cells(board, mine, others, board.connect - 1);
+        if threats.len() >= 2 {
+            score += sign * params.double_threat_bonus;
+        }
+        // a playable threat of the colour to move is completed right away
+        if colour == to_move && classify_threats(board, &threats, false).playable > 0 {
+            score += sign * params.immediate_threat;
+        }
+    }
+
+    score + params.center_weight * center_preference(board, piece)
+}
+
+fn sequence_weight(board: &BitBoard, len: u8, params: &EvalParams) -> i32 {
+    match len {
+        l if l == board.connect - 1 => params.weight_missing_one,
+        l if l == board.connect - 2 => params.weight_missing_two,
+        _ => params.weight_shorter,
+    }
+}
+
+// threats (empty cells completing a line) split by how useful they are
+#[derive(Default, Debug, PartialEq)]
+struct ThreatCounts {
+    playable: i32,      // the cell below is filled - can be played right now
+    good_parity: i32,   // on a row where the zugzwang at the end of the game favours the owner
+    bad_parity: i32,
+}
+
+// rows counted from 1 at the bottom: when the columns fill up, the first player gets the odd rows
+// and the second player the even rows - so odd threats are good for the first player, even for the second
+// (only holds for an even number of rows, otherwise every threat counts as bad parity)
+fn classify_threats(board: &BitBoard, threats: &HashSet<u8>, is_first_player: bool) -> ThreatCounts {
+    let stride = board.rows + 1;
+    let mut counts = ThreatCounts::default();
+
+    for &index in threats {
+        let (row, col) = (index % stride, index / stride);
+        // empty cells under a blocked cell can never be filled
+        if row >= board.rows || col >= board.cols || row < board.height(col) {
+            continue;
+        }
+
+        if row == board.height(col) {
+            counts.playable += 1;
+        } else if board.rows.is_multiple_of(2) && row.is_multiple_of(2) == is_first_player {
+            // row index 0, 2, 4.. is the odd row 1, 3, 5..
+            counts.good_parity += 1;
+        } else {
+            counts.bad_parity += 1;
+        }
+    }
+
+    counts
+}
+
+fn threat_parity_score(
+    board: &BitBoard,
+    piece: Piece,
+    to_move: Piece,
+    player_threats: &HashSet<u8>,
+    opponent_threats: &HashSet<u8>,
+    params: &EvalParams,
+) -> i32 {
+    let my_turn = piece == to_move;
+    let is_first_player = board.first == piece;
+
+    let mine = classify_threats(board, player_threats, is_first_player);
+    let theirs = classify_threats(board, opponent_threats, !is_first_player);
+
+    let mut score = 0;
+
+    // a playable threat wins for the side to move, two of them win for the other side (only one can be blocked)
+    if my_turn && mine.playable > 0 || !my_turn && mine.playable >= 2 && theirs.playable == 0 {
+        score += params.immediate_threat;
+    } else if !my_turn && theirs.playable > 0 || my_turn && theirs.playable >= 2 {
+        score -= params.immediate_threat;
+    }
+
+    score += params.threat_good_parity * (mine.good_parity - theirs.good_parity);
+    score += params.threat_bad_parity * (mine.bad_parity - theirs.bad_parity);
+
+    score
+}
+
+// the four line directions as bit distances: vertical, horizontal and both diagonals
+fn directions(board: &BitBoard) -> [u32; 4] {
+    let stride = board.rows as u32 + 1;
+    [1, stride, stride - 1, stride + 1]
+}
+
+// returns the empty cells right before or right after a run of `target_len` player pieces in direction `dir`
+// a step off the board always lands in a padding bit or above the used bits (or wraps on a cylinder),
+// so masking with the valid cells removes every cell that is not really on the board
+// blocked cells are never free, a run next to one is blocked on that side
+fn sequence_ends(board: &BitBoard, player: u128, opponent: u128, dir: u32, target_len: u8) -> u128 {
+    // m has a bit at the first cell of every run
+    let mut m = player;
+    for _ in 1..target_len {
+        m &= board.shift(m, dir, false);
+    }
+
+    let before = board.shift(m, dir, false);
+    let after = (0..target_len).fold(m, |cells, _| board.shift(cells, dir, true));
 
-    score + center_score
+    (before | after) & board.valid_cells() & !(player | opponent | board.blocked_mask)
 }
 
 fn count_unblocked_sequences(
@@ -64,23 +219,10 @@ fn count_unblocked_sequences(
     opponent: u128,
     target_len: u8,
 ) -> usize {
-    let stride = board.rows + 1;
-    let directions = [1, stride, stride - 1, stride + 1];
-    let mut count = 0;
-
-    for &dir in &directions {
-        let mut m = player;
-        for _ in 1..target_len {
-            m &= m >> dir;
-        }
-        let mask = player | opponent;
-        let potential1 = m >> dir;
-        let potential2 = m << (dir as u32 * target_len as u32);
-        let threats = (potential1 | potential2) & !mask;
-        count += threats.count_ones() as usize;
-    }
-
-    count
+    directions(board)
+        .iter()
+        .map(|&dir| sequence_ends(board, player, opponent, dir, target_len).count_ones() as usize)
+        .sum()
 }
 
 fn find_threat_cells(
@@ -89,25 +231,14 @@ fn find_threat_cells(
     opponent: u128,
     target_len: u8,
 ) -> HashSet<u8> {
-    let stride = board.rows + 1;
-    let directions = [1, stride, stride - 1, stride + 1];
     let mut threats = HashSet::new();
-    let mask = player | opponent;
-
-    for &dir in &directions {
-        let mut m = player;
-        for _ in 1..target_len {
-            m &= m >> dir;
-        }
 
-        let end1 = m >> dir;
-        let end2 = m << (dir as u32 * target_len as u32);
-        let free = (end1 | end2) & !mask;
+    for dir in directions(board) {
+        let mut free = sequence_ends(board, player, opponent, dir, target_len);
 
-        for i in 0..128 {
-            if (free >> i) & 1 != 0 {
-                threats.insert(i as u8);
-            }
+        while free != 0 {
+            threats.insert(free.trailing_zeros() as u8);
+            free &= free - 1;
         }
     }
 
@@ -115,11 +246,11 @@ fn find_threat_cells(
 }
 
 fn center_preference(board: &BitBoard, piece: Piece) -> i32 {
-    let bitboard = match piece {
-        Piece::Player => board.player_mask,
-        Piece::AI => board.ai_mask,
-        Piece::Empty => return 0,
-    };
+    // every column of a cylinder is in the middle
+    if board.variant.wraps() {
+        return 0;
+    }
+    let bitboard = board.mask(piece);
 
     let mid = board.cols as i32 / 2;
     let mut score = 0i32;
@@ -139,74 +270,142 @@ fn center_preference(board: &BitBoard, piece: Piece) -> i32 {
     score
 }
 
-pub fn minimax(
+// searches the best AI move - with a tie breaker the move is picked randomly among the (near) best ones
+pub fn best_move(
     board: &BitBoard,
     depth: u8,
-    alpha: i32,
-    beta: i32,
-    is_maximizing: bool
-) -> (Option<u8>, i32) {
-    let player = if is_maximizing { Piece::AI } else { Piece::Player };
-
-    if board.check_win(Piece::AI) {
-        return (None, 100_000_000);
-    } else if board.check_win(Piece::Player) {
-        return (None, -100_000_000);
-    } else if board.is_full() {
-        return (None, 0);
-    }
-    
-    if depth == 0 {
-        return (None, evaluate_heuristic(board, player));
-    }
-
-    let valid_moves = board.get_valid_locations();
-
-    if is_maximizing {
-        // ai turn - maximizing player
-        let mut best_score = i32::MIN;
-        let mut best_col = None;
-        let mut alpha = alpha;
-
-        for col in valid_moves {
-            let new_board = board.drop_piece(col, Piece::AI).unwrap();
-            let (_, score) = minimax(&new_board, depth - 1, alpha, beta, false);
-
-            if score > best_score {
-                best_score = score;
-                best_col = Some(col);
-            }
+    params: &EvalParams,
+    tie_break: Option<&mut TieBreak>,
+) -> (Option<Move>, i32) {
+    let info = Searcher::new(params.clone()).search(board, depth, tie_break, None);
+    (info.best_move, info.score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            alpha = max(alpha, score);
+    // (row, col) steps matching the bit directions [1, stride, stride - 1, stride + 1]
+    const STEPS: [(i32, i32); 4] = [(1, 0), (0, 1), (-1, 1), (1, 1)];
 
-            if alpha >= beta {
+    fn max_cols(rows: u8) -> u8 {
+        128 / (rows + 1) - 1
+    }
+
+    fn random_board(rows: u8, cols: u8, moves: usize, rng: &mut Rng) -> BitBoard {
+        let mut board = BitBoard::new(rows, cols, 2);
+        let mut piece = Piece::Player;
+
+        for _ in 0..moves {
+            let valid = board.get_valid_locations();
+            if valid.is_empty() {
                 break;
             }
+            board = board.drop_piece(valid[rng.below(valid.len())], piece).unwrap();
+            piece = if piece == Piece::Player { Piece::AI } else { Piece::Player };
         }
 
-        (best_col, best_score)
-    } else {
-        // player turn - minimizing player
-        let mut best_score = i32::MAX;
-        let mut best_col = None;
-        let mut beta = beta;
+        board
+    }
 
-        for col in valid_moves {
-            let new_board = board.drop_piece(col, Piece::Player).unwrap();
-            let (_, score) = minimax(&new_board, depth - 1, alpha, beta, true);
+    // cell by cell version of sequence_ends for one direction
+    fn naive_ends(board: &BitBoard, piece: Piece, (dr, dc): (i32, i32), len: u8) -> HashSet<u8> {
+        let on_board = |r: i32, c: i32| r >= 0 && c >= 0 && r < board.rows as i32 && c < board.cols as i32;
+        let mut ends = HashSet::new();
+
+        for r in 0..board.rows as i32 {
+            for c in 0..board.cols as i32 {
+                let is_run = (0..len as i32).all(|i| {
+                    let (rr, cc) = (r + i * dr, c + i * dc);
+                    on_board(rr, cc) && board.get_piece(rr as u8, cc as u8) == piece
+                });
+                if !is_run {
+                    continue;
+                }
+
+                for (rr, cc) in [(r - dr, c - dc), (r + len as i32 * dr, c + len as i32 * dc)] {
+                    if on_board(rr, cc) && board.get_piece(rr as u8, cc as u8) == Piece::Empty {
+                        ends.insert((cc * (board.rows as i32 + 1) + rr) as u8);
+                    }
+                }
+            }
+        }
 
-            if score < best_score {
-                best_score = score;
-                best_col = Some(col);
-            }            
+        ends
+    }
 
-            beta = min(beta, score);
+    fn bits(mut mask: u128) -> HashSet<u8> {
+        let mut set = HashSet::new();
+        while mask != 0 {
+            set.insert(mask.trailing_zeros() as u8);
+            mask &= mask - 1;
+        }
+        set
+    }
 
-            if alpha >= beta {
-                break;
+    #[test]
+    fn sequence_ends_match_naive_on_all_board_sizes() {
+        let mut rng = Rng::new(30);
+
+        for rows in 2..=20 {
+            for cols in 2..=max_cols(rows) {
+                let cells = rows as usize * cols as usize;
+                for moves in [cells / 3, cells * 2 / 3, cells] {
+                    let board = random_board(rows, cols, moves, &mut rng);
+
+                    for len in 1..=rows.min(cols) {
+                        for (dir, step) in directions(&board).into_iter().zip(STEPS) {
+                            let ends = sequence_ends(&board, board.player_mask, board.ai_mask, dir, len);
+                            assert_eq!(
+                                bits(ends),
+                                naive_ends(&board, Piece::Player, step, len),
+                                "{}x{} len {} step {:?}{}", rows, cols, len, step, board
+                            );
+                        }
+                    }
+                }
             }
         }
+    }
+
+    #[test]
+    fn counts_and_threats_match_naive() {
+        let mut rng = Rng::new(31);
+
+        for rows in [2, 4, 6, 9, 13, 20] {
+            for cols in [2, 3, 7, max_cols(rows)].into_iter().filter(|&c| c <= max_cols(rows)) {
+                for moves in 0..rows as usize * cols as usize {
+                    let board = random_board(rows, cols, moves, &mut rng);
 
-        (best_col, best_score)
+                    for len in 1..=rows.min(cols) {
+                        let naive: Vec<HashSet<u8>> = STEPS.iter()
+                            .map(|&step| naive_ends(&board, Piece::AI, step, len))
+                            .collect();
+
+                        let count: usize = naive.iter().map(|ends| ends.len()).sum();
+                        let union: HashSet<u8> = naive.into_iter().flatten().collect();
+
+                        assert_eq!(count_unblocked_sequences(&board, board.ai_mask, board.player_mask, len), count);
+                        assert_eq!(find_threat_cells(&board, board.ai_mask, board.player_mask, len), union);
+                    }
+                }
+            }
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn no_ends_outside_the_board() {
+        // full column of player pieces on the right edge of a tall board - the run would end above the top
+        let mut board = BitBoard::new(20, 5, 20);
+        for _ in 0..20 {
+            board = board.drop_piece(4, Piece::Player).unwrap();
+        }
+
+        for len in 1..=20 {
+            for dir in directions(&board) {
+                let ends = sequence_ends(&board, board.player_mask, board.ai_mask, dir, len);
+                assert_eq!(ends & !board.valid_cells(), 0);
+            }
+        }
+    }
+}
@@ -0,0 +1,434 @@
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::bitboard::{BitBoard, Move, Piece};
+use crate::rng::random_seed;
+use crate::variant::Variant;
+
+// version of the line protocol - both sides must speak the same one
+pub const PROTOCOL_VERSION: u32 = 2;
+
+// how long the other side has for its HELLO before the connection is dropped
+const HANDSHAKE_TIME: Duration = Duration::from_secs(5);
+
+// two people playing over TCP, one line per message:
+//   HELLO connect-4 <version> <id>   both sides, the guest first - the id of the game, 0 from a guest that has none yet
+//   SETTINGS rows=6 cols=7 ...       host -> guest, the board and rules of the game
+//   MOVES 3 4 p2                     host -> guest, the whole game so far (at the start and to resync)
+//   MOVE <ply> <move>                a move, numbered from 0 so that a side that missed one notices
+//   RESYNC                           guest -> host, asks for MOVES again
+//   ERROR <text>, BYE                the game cannot go on / the other side left
+// the host plays X (Piece::Player) and has the last word - after a reconnect the guest continues from its MOVES
+// the id is random and only known to the two sides, so only the guest of the game can take its seat again
+
+// which side of a network game this instance is
+#[derive(Clone, Debug, PartialEq)]
+pub enum NetRole {
+    Host(u16),      // listen on the port and pick the settings
+    Join(String),   // connect to host:port
+}
+
+#[derive(Debug)]
+pub enum NetError {
+    Io(io::Error),      // the connection failed or was lost - the game can be resumed by connecting again
+    Protocol(String),   // the other side sent something wrong (or ended the game) - no point in resuming
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetError::Io(err) => write!(f, "Connection error: {}", err),
+            NetError::Protocol(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+impl From<io::Error> for NetError {
+    fn from(err: io::Error) -> Self {
+        NetError::Io(err)
+    }
+}
+
+// board and rules, chosen by the host
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameSetup {
+    pub rows: u8,
+    pub cols: u8,
+    pub connect: u8,
+    pub variant: Variant,
+    pub misere: bool,
+    pub blocked: Vec<(u8, u8)>,
+    pub host_starts: bool,
+}
+
+impl GameSetup {
+    pub fn board(&self) -> BitBoard {
+        BitBoard::new(self.rows, self.cols, self.connect)
+            .with_variant(self.variant)
+            .with_misere(self.misere)
+            .with_blockers(&self.blocked)
+            .with_first(if self.host_starts { Piece::Player } else { Piece::AI })
+    }
+
+    // the settings come from the network, a bad size must not reach the board
+    fn validate(&self) -> Result<(), String> {
+        if !(2..=20).contains(&self.rows) || !(2..=BitBoard::max_cols(self.rows)).contains(&self.cols) {
+            return Err(format!("Invalid board size {}x{}", self.rows, self.cols));
+        }
+        if !(2..=self.rows.min(self.cols)).contains(&self.connect) {
+            return Err(format!("Invalid line length {}", self.connect));
+        }
+        if !self.variant.players().contains(&2) {
+            return Err(format!("{} cannot be played by two", self.variant));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    Hello(u32, u64),
+    Settings(GameSetup),
+    Moves(Vec<Move>),
+    Move(usize, Move),
+    Resync,
+    Error(String),
+    Bye,
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Message::Hello(version, id) => write!(f, "HELLO connect-4 {} {}", version, id),
+            Message::Settings(s) => {
+                let blocked: Vec<String> = s.blocked.iter().map(|(row, col)| format!("{}:{}", row, col)).collect();
+                write!(
+                    f,
+                    "SETTINGS rows={} cols={} connect={} variant={} misere={} first={} blocked={}",
+                    s.rows, s.cols, s.connect, s.variant.name(), s.misere as u8,
+                    if s.host_starts { "host" } else { "guest" },
+                    if blocked.is_empty() { "-".to_string() } else { blocked.join(",") },
+                )
+            }
+            Message::Moves(moves) => {
+                write!(f, "MOVES")?;
+                for mv in moves {
+                    write!(f, " {}", mv)?;
+                }
+                Ok(())
+            }
+            Message::Move(ply, mv) => write!(f, "MOVE {} {}", ply, mv),
+            Message::Resync => write!(f, "RESYNC"),
+            Message::Error(text) => write!(f, "ERROR {}", text),
+            Message::Bye => write!(f, "BYE"),
+        }
+    }
+}
+
+impl FromStr for Message {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("");
+
+        match command {
+            // an older version sends no id, it is refused by its version number
+            "HELLO" if words.next() == Some("connect-4") => {
+                let version = number(words.next(), line)?;
+                let id = match words.next() {
+                    Some(id) => number(Some(id), line)?,
+                    None => 0,
+                };
+                Ok(Message::Hello(version, id))
+            }
+            "SETTINGS" => parse_settings(words).map(Message::Settings),
+            "MOVES" => words.map(str::parse).collect::<Result<_, _>>().map(Message::Moves),
+            "MOVE" => {
+                let ply = number(words.next(), line)?;
+                let mv = words.next().ok_or_else(|| format!("Invalid message: {}", line.trim()))?.parse()?;
+                Ok(Message::Move(ply, mv))
+            }
+            "RESYNC" => Ok(Message::Resync),
+            "ERROR" => Ok(Message::Error(line.trim()["ERROR".len()..].trim().to_string())),
+            "BYE" => Ok(Message::Bye),
+            _ => Err(format!("Invalid message: {}", line.trim())),
+        }
+    }
+}
+
+fn number<T: FromStr>(word: Option<&str>, line: &str) -> Result<T, String> {
+    word.and_then(|w| w.parse().ok()).ok_or_else(|| format!("Invalid message: {}", line.trim()))
+}
+
+// key=value pairs in any order, unknown keys are skipped so that later versions can add some
+fn parse_settings<'a>(pairs: impl Iterator<Item = &'a str>) -> Result<GameSetup, String> {
+    let mut setup = GameSetup { rows: 0, cols: 0, connect: 0, variant: Variant::Classic, misere: false, blocked: Vec::new(), host_starts: true };
+    let invalid = |pair: &str| format!("Invalid setting: {}", pair);
+
+    for pair in pairs {
+        let (key, value) = pair.split_once('=').ok_or_else(|| invalid(pair))?;
+        match key {
+            "rows" => setup.rows = value.parse().map_err(|_| invalid(pair))?,
+            "cols" => setup.cols = value.parse().map_err(|_| invalid(pair))?,
+            "connect" => setup.connect = value.parse().map_err(|_| invalid(pair))?,
+            "variant" => setup.variant = value.parse()?,
+            "misere" => setup.misere = value == "1",
+            "first" => setup.host_starts = value != "guest",
+            "blocked" if value == "-" => setup.blocked.clear(),
+            "blocked" => {
+                setup.blocked = value.split(',')
+                    .map(|cell| {
+                        let (row, col) = cell.split_once(':').ok_or_else(|| invalid(pair))?;
+                        Ok((row.parse().map_err(|_| invalid(pair))?, col.parse().map_err(|_| invalid(pair))?))
+                    })
+                    .collect::<Result<_, String>>()?;
+            }
+            _ => {}
+        }
+    }
+
+    setup.validate()?;
+    Ok(setup)
+}
+
+// the game as both sides see it - every move is checked before it is played
+#[derive(Clone, Debug)]
+pub struct NetGame {
+    pub setup: GameSetup,
+    pub board: BitBoard,
+    pub moves: Vec<Move>,
+    pub to_move: Piece,
+    pub id: u64,    // chosen by the host, the guest gets it with the HELLO
+}
+
+impl NetGame {
+    pub fn new(setup: GameSetup) -> Self {
+        let board = setup.board();
+        Self { to_move: board.first, board, setup, moves: Vec::new(), id: game_id() }
+    }
+
+    // the game after the moves, fails on the first illegal one
+    pub fn replay(setup: GameSetup, moves: &[Move]) -> Result<Self, String> {
+        let mut game = NetGame::new(setup);
+        for &mv in moves {
+            game.play(mv)?;
+        }
+        Ok(game)
+    }
+
+    // plays the move for the side to move
+    pub fn play(&mut self, mv: Move) -> Result<(), String> {
+        if self.is_over() {
+            return Err(format!("Move {} after the end of the game", mv));
+        }
+        // drops go through drop_piece, pops through pop_piece - both refuse what the rules do not allow
+        self.board = self.board.play(mv, self.to_move).ok_or_else(|| format!("Illegal move {} for {}", mv, self.to_move))?;
+        self.moves.push(mv);
+        self.to_move = self.board.next_player(self.to_move);
+        Ok(())
+    }
+
+    pub fn winner(&self) -> Option<Piece> {
+        match self.moves.is_empty() {
+            true => None,
+            false => self.board.winner(self.board.previous_player(self.to_move)),
+        }
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.winner().is_some() || !self.board.has_legal_move(self.to_move)
+    }
+}
+
+// a random id that is never 0 - std seeds RandomState from the system, the clock alone would be easy to guess
+fn game_id() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(random_seed());
+    hasher.finish().max(1)
+}
+
+// waits for a connection until the deadline (or for ever without one)
+fn accept_until(listener: &TcpListener, deadline: Option<Instant>) -> io::Result<TcpStream> {
+    let Some(deadline) = deadline else {
+        return listener.accept().map(|(stream, _)| stream);
+    };
+
+    listener.set_nonblocking(true)?;
+    let accepted = loop {
+        match listener.accept() {
+            Ok((stream, _)) => break Ok(stream),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock && Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(50));
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => break Err(io::ErrorKind::TimedOut.into()),
+            Err(err) => break Err(err),
+        }
+    };
+    listener.set_nonblocking(false)?;
+
+    let stream = accepted?;
+    stream.set_nonblocking(false)?;
+    Ok(stream)
+}
+
+pub struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    pub me: Piece,  // Player for the host, AI for the guest
+}
+
+impl Connection {
+    fn new(stream: TcpStream, me: Piece) -> Result<Self, NetError> {
+        stream.set_nodelay(true)?;
+        Ok(Self { reader: BufReader::new(stream.try_clone()?), writer: stream, me })
+    }
+
+    fn send(&mut self, message: &Message) -> Result<(), NetError> {
+        writeln!(self.writer, "{}", message)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<Message, NetError> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(NetError::Io(io::ErrorKind::UnexpectedEof.into()));
+        }
+        match line.parse() {
+            Ok(Message::Error(text)) => Err(NetError::Protocol(format!("The other side reported: {}", text))),
+            Ok(Message::Bye) => Err(NetError::Protocol("The other side left the game".to_string())),
+            Ok(message) => Ok(message),
+            Err(err) => {
+                let _ = self.send(&Message::Error(err.clone()));
+                Err(NetError::Protocol(err))
+            }
+        }
+    }
+
+    // tells the other side what went wrong before giving up
+    fn fail<T>(&mut self, text: String) -> Result<T, NetError> {
+        let _ = self.send(&Message::Error(text.clone()));
+        Err(NetError::Protocol(text))
+    }
+
+    // waits for the guest of a new game and sends it the settings
+    pub fn accept(listener: &TcpListener, game: &NetGame) -> Result<Self, NetError> {
+        let stream = accept_until(listener, None)?;
+        Connection::welcome(stream, game, false)
+    }
+
+    // waits until the deadline for the guest to come back and sends it the game so far
+    // connections with another id (or none) are turned away and the host keeps waiting
+    pub fn accept_again(listener: &TcpListener, game: &NetGame, deadline: Instant) -> Result<Self, NetError> {
+        loop {
+            let stream = accept_until(listener, Some(deadline))?;
+            match Connection::welcome(stream, game, true) {
+                Ok(connection) => return Ok(connection),
+                Err(_) if Instant::now() < deadline => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn welcome(stream: TcpStream, game: &NetGame, resume: bool) -> Result<Self, NetError> {
+        // a side that connects and says nothing must not hold up the host
+        stream.set_read_timeout(Some(HANDSHAKE_TIME))?;
+        let mut connection = Connection::new(stream, Piece::Player)?;
+
+        match connection.receive()? {
+            Message::Hello(PROTOCOL_VERSION, id) if !resume || id == game.id => {}
+            Message::Hello(PROTOCOL_VERSION, _) => return connection.fail("This game is already being played".to_string()),
+            Message::Hello(version, _) => return connection.fail(format!("Protocol version {} is not supported, expected {}", version, PROTOCOL_VERSION)),
+            other => return connection.fail(format!("Expected HELLO, got {}", other)),
+        }
+        connection.writer.set_read_timeout(None)?;
+        connection.send(&Message::Hello(PROTOCOL_VERSION, game.id))?;
+        connection.send(&Message::Settings(game.setup.clone()))?;
+        connection.send(&Message::Moves(game.moves.clone()))?;
+        Ok(connection)
+    }
+
+    // connects to a host and takes over its game
+    pub fn join<A: ToSocketAddrs>(address: A) -> Result<(Self, NetGame), NetError> {
+        Connection::join_game(address, 0)
+    }
+
+    // connects to the host of the game again after the connection was lost
+    pub fn join_again<A: ToSocketAddrs>(address: A, game: &NetGame) -> Result<(Self, NetGame), NetError> {
+        let (connection, synced) = Connection::join_game(address, game.id)?;
+        match synced.setup == game.setup {
+            true => Ok((connection, synced)),
+            false => Err(NetError::Protocol("The host is playing another game".to_string())),
+        }
+    }
+
+    fn join_game<A: ToSocketAddrs>(address: A, id: u64) -> Result<(Self, NetGame), NetError> {
+        let mut connection = Connection::new(TcpStream::connect(address)?, Piece::AI)?;
+        connection.send(&Message::Hello(PROTOCOL_VERSION, id))?;
+
+        let id = match connection.receive()? {
+            Message::Hello(PROTOCOL_VERSION, host_id) if id == 0 || id == host_id => host_id,
+            other => return connection.fail(format!("Expected HELLO connect-4 {}, got {}", PROTOCOL_VERSION, other)),
+        };
+        let setup = match connection.receive()? {
+            Message::Settings(setup) => setup,
+            other => return connection.fail(format!("Expected SETTINGS, got {}", other)),
+        };
+        let game = match connection.receive()? {
+            Message::Moves(moves) => NetGame::replay(setup, &moves),
+            other => return connection.fail(format!("Expected MOVES, got {}", other)),
+        };
+
+        match game {
+            Ok(game) => Ok((connection, NetGame { id, ..game })),
+            Err(err) => connection.fail(err),
+        }
+    }
+
+    // sends the last move of the game (played by this side)
+    pub fn send_move(&mut self, game: &NetGame) -> Result<(), NetError> {
+        match game.moves.last() {
+            Some(&mv) => self.send(&Message::Move(game.moves.len() - 1, mv)),
+            None => Ok(()),
+        }
+    }
+
+    // waits until it is this side's turn again, playing the moves of the other side
+    // a move with the wrong number means one side missed something: the guest asks for the game again
+    // and continues from the host's version of it
+    pub fn receive_move(&mut self, game: &mut NetGame) -> Result<(), NetError> {
+        while game.to_move != self.me && !game.is_over() {
+            match self.receive()? {
+                Message::Move(ply, mv) if ply == game.moves.len() => {
+                    if let Err(err) = game.play(mv) {
+                        return self.fail(err);
+                    }
+                }
+                Message::Move(..) | Message::Resync if self.me == Piece::Player => {
+                    self.send(&Message::Moves(game.moves.clone()))?;
+                }
+                Message::Move(..) => self.send(&Message::Resync)?,
+                // an older copy of the game (the answer to an earlier RESYNC) changes nothing
+                Message::Moves(moves) if self.me == Piece::AI && game.moves.starts_with(&moves) => {}
+                Message::Moves(moves) if self.me == Piece::AI => match NetGame::replay(game.setup.clone(), &moves) {
+                    Ok(synced) => *game = NetGame { id: game.id, ..synced },
+                    Err(err) => return self.fail(err),
+                },
+                other => return self.fail(format!("Unexpected message during the game: {}", other)),
+            }
+        }
+        Ok(())
+    }
+
+    // ends the game politely
+    pub fn close(mut self) {
+        let _ = self.send(&Message::Bye);
+    }
+}
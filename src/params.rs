@@ -0,0 +1,99 @@
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::minimax::WIN_SCORE;
+
+// the largest weight - the evaluation multiplies weights by counts of sequences or threats, which are at
+// most 4 directions times the 128 cells of a board, and no product may reach WIN_SCORE: it would overflow
+// or look like a won position and end the search early
+pub const MAX_WEIGHT: i32 = WIN_SCORE / (4 * 128);
+
+// weights used by the heuristic evaluation - defaults are the hand-picked values
+// can be loaded from a TOML file (missing keys keep their default) and overridden from the command line
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EvalParams {
+    pub win_score: i32,             // score of a won position
+    pub double_threat_bonus: i32,   // bonus for having 2 or more cells that complete a line
+    pub weight_missing_one: i32,    // weight of an open sequence of length connect-1
+    pub weight_missing_two: i32,    // weight of an open sequence of length connect-2
+    pub weight_shorter: i32,        // weight of shorter open sequences
+    pub center_weight: i32,         // multiplier of the center column preference
+    pub immediate_threat: i32,      // a threat that can be completed on the next move and not stopped
+    pub threat_good_parity: i32,    // threat on a row that favours its owner (odd rows for the first player)
+    pub threat_bad_parity: i32,     // any other threat that is not playable yet
+    pub kept_piece: i32,            // Pop 10: value of one kept piece
+}
+
+impl Default for EvalParams {
+    fn default() -> Self {
+        Self {
+            win_score: 1_000_000,
+            double_threat_bonus: 5000,
+            weight_missing_one: 1000,
+            weight_missing_two: 100,
+            weight_shorter: 10,
+            center_weight: 1,
+            immediate_threat: 100_000,
+            threat_good_parity: 800,
+            threat_bad_parity: 100,
+            kept_piece: 20_000,
+        }
+    }
+}
+
+impl EvalParams {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("Cannot read {}: {}", path.display(), err))?;
+        Self::from_toml(&text).map_err(|err| format!("Invalid parameter file {}: {}", path.display(), err))
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        let params: Self = toml::from_str(text).map_err(|err: toml::de::Error| err.message().to_string())?;
+        params.validate()?;
+        Ok(params)
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("parameters are always serializable")
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, self.to_toml()).map_err(|err| format!("Cannot write {}: {}", path.display(), err))
+    }
+
+    // sets one parameter by name, e.g. set("win_score", "500000")
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let value: i32 = value.trim().parse()
+            .map_err(|_| format!("Invalid value for {}: {}", key, value))?;
+
+        // round trip through a TOML table, so the names always match the struct fields
+        let mut table = toml::Table::try_from(&*self).expect("parameters are always serializable");
+        if !table.contains_key(key) {
+            return Err(format!("Unknown evaluation parameter: {}", key));
+        }
+        table.insert(key.to_string(), toml::Value::Integer(value as i64));
+        let params: Self = table.try_into().map_err(|err: toml::de::Error| err.message().to_string())?;
+        params.validate()?;
+        *self = params;
+
+        Ok(())
+    }
+
+    // every weight within MAX_WEIGHT either way - win_score is not multiplied, it only has to stay below
+    // the score of a won position in the search
+    fn validate(&self) -> Result<(), String> {
+        let table = toml::Table::try_from(self).expect("parameters are always serializable");
+        for (key, value) in &table {
+            let limit = if key == "win_score" { WIN_SCORE - 1 } else { MAX_WEIGHT };
+            match value.as_integer() {
+                Some(value) if value.abs() <= limit as i64 => {}
+                _ => return Err(format!("{} must be between {} and {}", key, -limit, limit)),
+            }
+        }
+        Ok(())
+    }
+}
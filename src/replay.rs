@@ -0,0 +1,127 @@
+use std::time::Duration;
+
+use crate::bitboard::{BitBoard, Move, Piece};
+use crate::engine::SteppedSearch;
+use crate::search::{SearchInfo, Searcher};
+use crate::stats::{GameRecord, Stats};
+use crate::variant::Variant;
+
+// thinking time of the evaluation shown at each step of a replay
+const EVAL_TIME: Duration = Duration::from_millis(300);
+
+// a recorded game that can be stepped through - every position is kept, so going back is free
+pub struct Replay {
+    pub record: GameRecord,
+    pub moves: Vec<Move>,
+    boards: Vec<BitBoard>,  // boards[i] is the position after i moves
+    movers: Vec<Piece>,     // movers[i] made move i (and is to move on boards[i])
+    pub position: usize,    // number of moves shown
+}
+
+impl Replay {
+    pub fn new(record: &GameRecord) -> Result<Self, String> {
+        let variant: Variant = record.variant.parse()?;
+        if record.history.is_empty() && record.moves > 0 {
+            return Err("The game was recorded without its moves".to_string());
+        }
+
+        let mut board = BitBoard::new(record.rows, record.cols, record.connect)
+            .with_variant(variant)
+            .with_players(record.players)
+            .with_misere(record.misere)
+            .with_blockers(&record.blocked)
+            .with_first(if record.player_started { Piece::Player } else { Piece::AI });
+        let mut to_move = board.first;
+        let (mut moves, mut boards, mut movers) = (Vec::new(), vec![board.clone()], vec![to_move]);
+
+        for word in record.history.split_whitespace() {
+            let mv: Move = word.parse()?;
+            board = board.play(mv, to_move).ok_or_else(|| format!("Illegal move {} for {} after {} moves", mv, to_move, moves.len()))?;
+            to_move = board.next_player(to_move);
+            moves.push(mv);
+            boards.push(board.clone());
+            movers.push(to_move);
+        }
+
+        Ok(Self { record: record.clone(), moves, boards, movers, position: 0 })
+    }
+
+    // game `number` of the store, counted from 1 as `stats` lists them - the last one without a number
+    pub fn from_stats(stats: &Stats, number: Option<usize>) -> Result<Self, String> {
+        let number = number.unwrap_or(stats.games.len());
+        let record = number.checked_sub(1).and_then(|i| stats.games.get(i))
+            .ok_or_else(|| match stats.games.len() {
+                0 => "No games recorded yet".to_string(),
+                count => format!("There is no game {} - {} games are recorded", number, count),
+            })?;
+        Self::new(record)
+    }
+
+    pub fn board(&self) -> &BitBoard {
+        &self.boards[self.position]
+    }
+
+    pub fn to_move(&self) -> Piece {
+        self.movers[self.position]
+    }
+
+    pub fn len(&self) -> usize {
+        self.moves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty()
+    }
+
+    // the move that led to the shown position, with its number (from 1) and who made it
+    pub fn last_move(&self) -> Option<(usize, Piece, Move)> {
+        let index = self.position.checked_sub(1)?;
+        Some((self.position, self.movers[index], self.moves[index]))
+    }
+
+    // "Move 5/21: AI drops into column 3"
+    pub fn describe(&self) -> String {
+        match self.last_move() {
+            None => format!("Start ({} moves)", self.len()),
+            Some((number, piece, Move::Drop(col))) => format!("Move {}/{}: {} drops into column {}", number, self.len(), piece, col),
+            Some((number, piece, Move::Pop(col))) => format!("Move {}/{}: {} pops column {}", number, self.len(), piece, col),
+        }
+    }
+
+    pub fn forward(&mut self) {
+        self.go_to(self.position + 1);
+    }
+
+    pub fn back(&mut self) {
+        self.go_to(self.position.saturating_sub(1));
+    }
+
+    // any move number, beyond the end is the end
+    pub fn go_to(&mut self, position: usize) {
+        self.position = position.min(self.len());
+    }
+
+    // the engine's view of the shown position, for the colour to move - nothing once the game is over
+    pub fn evaluate(&self, searcher: &mut Searcher, depth: u8) -> Option<SearchInfo> {
+        if self.is_over() {
+            return None;
+        }
+        let time_limit = searcher.time_limit.replace(EVAL_TIME);
+        let info = searcher.search_for(self.board(), self.to_move(), depth, None, None);
+        searcher.time_limit = time_limit;
+        Some(info)
+    }
+
+    // the same split into steps, for the GUI to search between frames
+    pub fn evaluation(&self, depth: u8) -> Option<SteppedSearch> {
+        match self.is_over() {
+            true => None,
+            false => Some(SteppedSearch::new(self.board(), self.to_move(), depth).with_time_limit(Some(EVAL_TIME))),
+        }
+    }
+
+    fn is_over(&self) -> bool {
+        let board = self.board();
+        self.last_move().is_some_and(|(_, piece, _)| board.winner(piece).is_some()) || !board.has_legal_move(self.to_move())
+    }
+}
@@ -0,0 +1,464 @@
+use std::cmp::{max, min};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// std's clock panics in the browser, web-time is the same on native builds
+use web_time::Instant;
+
+use crate::bitboard::{BitBoard, Move, Piece, PositionKey};
+use crate::minimax::{evaluate_heuristic, TieBreak, WIN_SCORE};
+use crate::params::EvalParams;
+use crate::variant::Variant;
+
+// the table is cleared when it would grow over this many positions - an entry takes 112 bytes and the map
+// has up to twice as many slots as entries, so about 120 MB
+pub const MAX_TT_ENTRIES: usize = 1 << 19;
+
+// result of a search, also reported after every finished depth
+#[derive(Clone, Debug, Default)]
+pub struct SearchInfo {
+    pub best_move: Option<Move>,
+    pub score: i32,
+    pub depth: u8,          // deepest fully searched depth
+    pub nodes: u64,
+    pub cutoffs: u64,       // alpha-beta cutoffs
+    pub tt_hits: u64,       // positions answered by the transposition table
+    pub elapsed: Duration,
+    pub pv: Vec<Move>,      // principal variation, starting with the AI move
+    // score of every move at the deepest finished depth - the best one is exact, the others are only
+    // upper bounds, except after `analyze`
+    pub move_scores: Vec<(Move, i32)>,
+}
+
+impl SearchInfo {
+    pub fn nodes_per_second(&self) -> u64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 { (self.nodes as f64 / secs) as u64 } else { 0 }
+    }
+
+    // score as text: "win", "loss" or the heuristic value
+    pub fn score_text(&self) -> String {
+        match self.score {
+            s if s >= WIN_SCORE => "win".to_string(),
+            s if s <= -WIN_SCORE => "loss".to_string(),
+            s => s.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for SearchInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pv: Vec<String> = self.pv.iter().map(|m| m.to_string()).collect();
+        write!(
+            f,
+            "depth {}, score {}, nodes {} ({}/s), cutoffs {}, TT hits {}, {:.2}s, pv {}",
+            self.depth, self.score_text(), self.nodes, self.nodes_per_second(),
+            self.cutoffs, self.tt_hits, self.elapsed.as_secs_f64(), pv.join(" ")
+        )
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Bound {
+    Exact,
+    Lower,  // real score >= stored score (beta cutoff)
+    Upper,  // real score <= stored score (no move raised alpha)
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    depth: u8,
+    score: i32,
+    bound: Bound,
+    best: Option<Move>,
+}
+
+// the position, the colour to move and the colour the scores are for - no hashing collisions possible
+type Key = (PositionKey, Piece, Piece);
+
+// iterative deepening alpha-beta search with a transposition table
+// the table is kept between searches, so one Searcher should be used for a whole game
+// with more than two players the search is paranoid: every colour outside the searching team minimizes
+pub struct Searcher {
+    pub params: EvalParams,
+    pub time_limit: Option<Duration>,  // thinking time per search, the deepest finished depth is played
+    pub table_limit: usize,             // positions kept in the table
+    tt: HashMap<Key, Entry>,
+    board_kind: (u8, u8, u8, Variant, bool, u128, Piece),
+    info: SearchInfo,
+    stop: Arc<AtomicBool>,  // set from another thread to end the search early
+    can_stop: bool,         // false until the first depth is done, so there is always a move
+    me: Piece,              // colour the current search is for
+    deadline: Option<Instant>,
+    out_of_time: bool,
+    exact_scores: bool,     // every root move is searched with the full window
+}
+
+impl Searcher {
+    pub fn new(params: EvalParams) -> Self {
+        Self {
+            params,
+            tt: HashMap::new(),
+            board_kind: (0, 0, 0, Variant::Classic, false, 0, Piece::Player),
+            info: SearchInfo::default(),
+            stop: Arc::new(AtomicBool::new(false)),
+            can_stop: false,
+            me: Piece::AI,
+            time_limit: None,
+            table_limit: MAX_TT_ENTRIES,
+            deadline: None,
+            out_of_time: false,
+            exact_scores: false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.tt.clear();
+    }
+
+    pub fn table_len(&self) -> usize {
+        self.tt.len()
+    }
+
+    // flag that stops a running search - the owner resets it before starting the next one
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    fn stopped(&self) -> bool {
+        self.can_stop && (self.out_of_time || self.stop.load(Ordering::Relaxed))
+    }
+
+    // prepares the table for the board, it only identifies positions on boards of the same size, rules, blocked
+    // cells and first colour (the scores depend on it)
+    fn prepare(&mut self, board: &BitBoard) {
+        let kind = (board.rows, board.cols, board.connect, board.variant, board.misere, board.blocked_mask, board.first);
+        if self.board_kind != kind {
+            self.tt.clear();
+            self.board_kind = kind;
+        }
+    }
+
+    // the limit holds during a search too - pondering and long analyses would fill any memory
+    fn store(&mut self, key: Key, entry: Entry) {
+        if self.tt.len() >= self.table_limit && !self.tt.contains_key(&key) {
+            self.tt.clear();
+        }
+        self.tt.insert(key, entry);
+    }
+
+    // searches the position with the player to move (while the human thinks), filling the table
+    // with the positions after every reply - returns the deepest finished depth
+    // (for the AI, the colour that plays after the player)
+    pub fn ponder(&mut self, board: &BitBoard, max_depth: u8) -> u8 {
+        self.prepare(board);
+        self.info = SearchInfo::default();
+        self.can_stop = true;
+        self.me = Piece::AI;
+        self.deadline = None;
+        self.out_of_time = false;
+
+        if board.winner(board.previous_player(Piece::Player)).is_some() || !board.has_legal_move(Piece::Player) {
+            return 0;
+        }
+
+        let mut finished = 0;
+        for depth in 1..=max_depth {
+            self.alpha_beta(board, depth, i32::MIN, i32::MAX, Piece::Player);
+            if self.stopped() {
+                break;
+            }
+            finished = depth;
+        }
+
+        finished
+    }
+
+    // searches the best AI move with depths 1, 2, ... max_depth
+    // `progress` is called after every finished depth
+    pub fn search(
+        &mut self,
+        board: &BitBoard,
+        max_depth: u8,
+        tie_break: Option<&mut TieBreak>,
+        progress: Option<&mut dyn FnMut(&SearchInfo)>,
+    ) -> SearchInfo {
+        self.search_for(board, Piece::AI, max_depth, tie_break, progress)
+    }
+
+    // like search_for, but with the exact score of every move in `move_scores` (slower, nothing is cut
+    // off at the root)
+    pub fn analyze(&mut self, board: &BitBoard, me: Piece, max_depth: u8) -> SearchInfo {
+        self.exact_scores = true;
+        let info = self.search_for(board, me, max_depth, None, None);
+        self.exact_scores = false;
+        info
+    }
+
+    // searches the best move of the colour `me` (to move on the board)
+    pub fn search_for(
+        &mut self,
+        board: &BitBoard,
+        me: Piece,
+        max_depth: u8,
+        mut tie_break: Option<&mut TieBreak>,
+        mut progress: Option<&mut dyn FnMut(&SearchInfo)>,
+    ) -> SearchInfo {
+        let start = Instant::now();
+        let deadline = self.time_limit.map(|limit| start + limit);
+        let mut info = SearchInfo::default();
+
+        for depth in 1..=max_depth.max(1) {
+            let Some(last) = self.search_depth(board, me, depth, &mut info, tie_break.as_deref_mut(), deadline) else {
+                break;
+            };
+            info.elapsed = start.elapsed();
+            if let Some(callback) = progress.as_mut() {
+                callback(&info);
+            }
+            if last {
+                break;
+            }
+        }
+
+        info.elapsed = start.elapsed();
+        info
+    }
+
+    // one depth of the iterative deepening, the result goes to `info` - search_for runs them all at once,
+    // SteppedSearch one after the other between frames
+    // returns None when the deadline came first (the finished subtrees stay in the table, so the same depth
+    // goes faster the next time), Some(true) when a deeper search would change nothing
+    pub fn search_depth(
+        &mut self,
+        board: &BitBoard,
+        me: Piece,
+        depth: u8,
+        info: &mut SearchInfo,
+        tie_break: Option<&mut TieBreak>,
+        deadline: Option<Instant>,
+    ) -> Option<bool> {
+        std::mem::swap(&mut self.info, info);
+        self.me = me;
+        self.deadline = deadline;
+        self.out_of_time = false;
+        self.can_stop = depth > 1;
+        self.prepare(board);
+
+        let margin = match self.exact_scores {
+            true => Some(i32::MAX),
+            false => tie_break.as_ref().map(|tb| tb.margin),
+        };
+        let scored = self.search_root(board, depth, margin);
+
+        // an unfinished depth is thrown away
+        let result = match self.stopped() {
+            true => None,
+            false => {
+                let (best_move, score) = match tie_break {
+                    Some(tb) if !scored.is_empty() => pick_near_best(&scored, tb),
+                    _ => scored.iter().copied().fold((None, i32::MIN), |best, (mv, score)| {
+                        if score > best.1 { (Some(mv), score) } else { best }
+                    }),
+                };
+
+                self.info.best_move = best_move;
+                self.info.score = if scored.is_empty() { self.terminal_score(board) } else { score };
+                self.info.depth = depth;
+                self.info.pv = self.principal_variation(board, best_move, depth);
+                self.info.move_scores = scored.clone();
+
+                // nothing left to search or the result is already decided (for every move when analyzing)
+                let decided = match self.exact_scores {
+                    true => scored.iter().all(|&(_, score)| score.abs() >= WIN_SCORE),
+                    false => self.info.score.abs() >= WIN_SCORE,
+                };
+                Some(scored.is_empty() || decided)
+            }
+        };
+
+        std::mem::swap(&mut self.info, info);
+        result
+    }
+
+    // returns the score of every root move, moves within `margin` of the best one get exact scores
+    fn search_root(&mut self, board: &BitBoard, depth: u8, margin: Option<i32>) -> Vec<(Move, i32)> {
+        self.info.nodes += 1;
+        let me = self.me;
+
+        if board.winner(board.previous_player(me)).is_some() || !board.has_legal_move(me) {
+            return Vec::new();
+        }
+
+        let key = (board.position_key(), me, me);
+        let tt_move = self.tt.get(&key).and_then(|e| e.best);
+
+        let mut scored = Vec::new();
+        let mut best_score = i32::MIN;
+        let mut best_move = None;
+
+        for mv in ordered_moves(board, me, tt_move) {
+            let new_board = board.play(mv, me).unwrap();
+            let alpha = match margin {
+                // lowered by the margin, so near-best moves still get exact scores
+                Some(margin) => best_score.saturating_sub(margin.saturating_add(1)),
+                None => best_score,
+            };
+            let score = self.alpha_beta(&new_board, depth - 1, alpha, i32::MAX, board.next_player(me));
+            if self.stopped() {
+                return scored;
+            }
+
+            if score > best_score {
+                best_score = score;
+                best_move = Some(mv);
+            }
+            scored.push((mv, score));
+        }
+
+        self.store(key, Entry { depth, score: best_score, bound: Bound::Exact, best: best_move });
+        scored
+    }
+
+    fn alpha_beta(&mut self, board: &BitBoard, depth: u8, mut alpha: i32, mut beta: i32, to_move: Piece) -> i32 {
+        if self.stopped() {
+            return 0;
+        }
+        self.info.nodes += 1;
+        // the clock is only read now and then, it is slow compared to a node
+        if self.info.nodes.is_multiple_of(1024) && self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            self.out_of_time = true;
+        }
+
+        match board.winner(board.previous_player(to_move)) {
+            Some(winner) if board.same_team(winner, self.me) => return WIN_SCORE,
+            Some(_) => return -WIN_SCORE,
+            None if !board.has_legal_move(to_move) => return 0,
+            None => {}
+        }
+
+        if depth == 0 {
+            return evaluate_heuristic(board, self.me, to_move, &self.params);
+        }
+
+        let is_maximizing = board.same_team(to_move, self.me);
+        let key = (board.position_key(), to_move, self.me);
+        let mut tt_move = None;
+        if let Some(entry) = self.tt.get(&key) {
+            tt_move = entry.best;
+            if entry.depth >= depth {
+                match entry.bound {
+                    Bound::Exact => {
+                        self.info.tt_hits += 1;
+                        return entry.score;
+                    }
+                    Bound::Lower => alpha = max(alpha, entry.score),
+                    Bound::Upper => beta = min(beta, entry.score),
+                }
+                if alpha >= beta {
+                    self.info.tt_hits += 1;
+                    return entry.score;
+                }
+            }
+        }
+
+        let (alpha_start, beta_start) = (alpha, beta);
+        let mut best_score = if is_maximizing { i32::MIN } else { i32::MAX };
+        let mut best_move = None;
+
+        for mv in ordered_moves(board, to_move, tt_move) {
+            let new_board = board.play(mv, to_move).unwrap();
+            let score = self.alpha_beta(&new_board, depth - 1, alpha, beta, board.next_player(to_move));
+            // the score of a stopped search is meaningless, nothing is stored
+            if self.stopped() {
+                return 0;
+            }
+
+            if is_maximizing && score > best_score || !is_maximizing && score < best_score {
+                best_score = score;
+                best_move = Some(mv);
+            }
+
+            if is_maximizing {
+                alpha = max(alpha, score);
+            } else {
+                beta = min(beta, score);
+            }
+
+            if alpha >= beta {
+                self.info.cutoffs += 1;
+                break;
+            }
+        }
+
+        let bound = if best_score <= alpha_start {
+            Bound::Upper
+        } else if best_score >= beta_start {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        self.store(key, Entry { depth, score: best_score, bound, best: best_move });
+
+        best_score
+    }
+
+    // score of a finished game at the root
+    fn terminal_score(&self, board: &BitBoard) -> i32 {
+        match board.winner(board.previous_player(self.me)) {
+            Some(winner) if board.same_team(winner, self.me) => WIN_SCORE,
+            Some(_) => -WIN_SCORE,
+            None => 0,
+        }
+    }
+
+    // follows the stored best moves from the root
+    fn principal_variation(&self, board: &BitBoard, first: Option<Move>, depth: u8) -> Vec<Move> {
+        let mut pv = Vec::new();
+        let mut board = board.clone();
+        let mut next = first;
+        let mut piece = self.me;
+
+        while let Some(mv) = next {
+            board = match board.play(mv, piece) {
+                Some(b) => b,
+                None => break,
+            };
+            pv.push(mv);
+
+            if pv.len() >= depth as usize || board.winner(piece).is_some() || !board.has_legal_move(board.next_player(piece)) {
+                break;
+            }
+            piece = board.next_player(piece);
+            next = self.tt.get(&(board.position_key(), piece, self.me)).and_then(|e| e.best);
+        }
+
+        pv
+    }
+}
+
+// the move from the table first, then drops from the center outwards, then pops
+fn ordered_moves(board: &BitBoard, piece: Piece, first: Option<Move>) -> Vec<Move> {
+    let center = board.cols as i32 / 2;
+    let mut moves = board.legal_moves(piece);
+    moves.sort_by_key(|&mv| (matches!(mv, Move::Pop(_)), (mv.col() as i32 - center).abs(), mv.col()));
+
+    if let Some(pos) = first.and_then(|f| moves.iter().position(|&mv| mv == f)) {
+        let mv = moves.remove(pos);
+        moves.insert(0, mv);
+    }
+
+    moves
+}
+
+fn pick_near_best(scored: &[(Move, i32)], tie_break: &mut TieBreak) -> (Option<Move>, i32) {
+    let best = scored.iter().map(|&(_, s)| s).max().unwrap();
+    let threshold = best.saturating_sub(tie_break.margin);
+    let candidates: Vec<(Move, i32)> = scored.iter().copied().filter(|&(_, s)| s >= threshold).collect();
+    let (mv, score) = candidates[tie_break.pick(candidates.len())];
+
+    (Some(mv), score)
+}
@@ -0,0 +1,250 @@
+use std::io::Read;
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tiny_http::{Header, Request, Response, Server};
+
+use crate::bitboard::{BitBoard, Move, Piece};
+use crate::params::EvalParams;
+use crate::search::{SearchInfo, Searcher};
+use crate::variant::Variant;
+
+// HTTP/JSON interface for web front-ends (built with `--features server`, run with `--server ADDR`)
+//   POST /analyze   best move, score of every move and the principal variation
+//   POST /move      the engine's move, and the position after it
+// both take the position as JSON - only "moves" is needed for a classic 6x7 board:
+//   {"rows": 6, "cols": 7, "connect": 4, "variant": "classic", "misere": false, "players": 2,
+//    "blocked": [[0, 3]], "moves": [3, 3, "p2"], "depth": 10, "time_ms": 1000}
+// requests are answered one after another, the searcher (and its table) is kept between them
+// a query string after the path is ignored, bodies over MAX_BODY are answered with 413
+
+// depth and thinking time when the request has none, and the most a request may ask for
+const DEFAULT_DEPTH: u8 = 10;
+const MAX_DEPTH: u8 = 30;
+const DEFAULT_TIME_MS: u64 = 1000;
+const MAX_TIME_MS: u64 = 10_000;
+
+// the largest request body that is read - a long game is a few kilobytes
+const MAX_BODY: usize = 64 * 1024;
+
+// a move is a column number or the text form ("3", "p3")
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MoveValue {
+    Column(u8),
+    Text(String),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PositionRequest {
+    rows: Option<u8>,
+    cols: Option<u8>,
+    connect: Option<u8>,
+    #[serde(default)]
+    variant: Option<String>,
+    #[serde(default)]
+    misere: bool,
+    players: Option<u8>,
+    #[serde(default)]
+    blocked: Vec<(u8, u8)>,
+    #[serde(default)]
+    moves: Vec<MoveValue>,
+    depth: Option<u8>,
+    time_ms: Option<u64>,
+}
+
+// the position of a request, with the colour to move
+struct Position {
+    board: BitBoard,
+    to_move: Piece,
+    moves: Vec<Move>,
+}
+
+impl PositionRequest {
+    fn position(&self) -> Result<Position, String> {
+        let variant: Variant = match &self.variant {
+            Some(name) => name.parse()?,
+            None => Variant::Classic,
+        };
+        let (rows, cols, connect) = variant.default_size();
+        let (rows, cols, connect) = (self.rows.unwrap_or(rows), self.cols.unwrap_or(cols), self.connect.unwrap_or(connect));
+        if !(2..=20).contains(&rows) || !(2..=BitBoard::max_cols(rows)).contains(&cols) {
+            return Err(format!("Invalid board size {}x{}", rows, cols));
+        }
+        if !(2..=rows.min(cols)).contains(&connect) {
+            return Err(format!("Invalid line length {}", connect));
+        }
+        let players = self.players.unwrap_or(*variant.players().start());
+        if !variant.players().contains(&players) {
+            return Err(format!("{} is played by {} to {} players", variant, variant.players().start(), variant.players().end()));
+        }
+
+        let mut board = BitBoard::new(rows, cols, connect)
+            .with_variant(variant)
+            .with_players(players)
+            .with_misere(self.misere)
+            .with_blockers(&self.blocked);
+        let mut to_move = Piece::Player;
+        let mut moves = Vec::new();
+
+        for value in &self.moves {
+            let mv = match value {
+                MoveValue::Column(col) => Move::Drop(*col),
+                MoveValue::Text(text) => text.parse()?,
+            };
+            if board.winner(board.previous_player(to_move)).is_some() {
+                return Err(format!("Move {} after the end of the game", mv));
+            }
+            board = board.play(mv, to_move).ok_or_else(|| format!("Illegal move {} for {}", mv, to_move))?;
+            moves.push(mv);
+            to_move = board.next_player(to_move);
+        }
+
+        Ok(Position { board, to_move, moves })
+    }
+
+    fn limits(&self) -> (u8, Duration) {
+        let depth = self.depth.unwrap_or(DEFAULT_DEPTH).clamp(1, MAX_DEPTH);
+        let time = self.time_ms.unwrap_or(DEFAULT_TIME_MS).min(MAX_TIME_MS);
+        (depth, Duration::from_millis(time))
+    }
+}
+
+// answers one request - returns the status code and the JSON body
+pub fn handle(searcher: &mut Searcher, method: &str, url: &str, body: &str) -> (u16, Value) {
+    // the query string is not used
+    let path = url.split_once('?').map_or(url, |(path, _)| path);
+    let endpoint = match path {
+        "/analyze" | "/move" => path,
+        _ => return (404, json!({ "error": format!("Unknown endpoint {} (use POST /analyze or POST /move)", path) })),
+    };
+    if method != "POST" {
+        return (405, json!({ "error": format!("{} needs POST", endpoint) }));
+    }
+
+    let request: PositionRequest = match serde_json::from_str(body) {
+        Ok(request) => request,
+        Err(err) => return (400, json!({ "error": format!("Invalid request: {}", err) })),
+    };
+    let position = match request.position() {
+        Ok(position) => position,
+        Err(err) => return (400, json!({ "error": err })),
+    };
+
+    let state = game_state(&position);
+    if state["game_over"] == true {
+        return (409, json!({ "error": "The game is over", "state": state }));
+    }
+
+    let (depth, time) = request.limits();
+    searcher.time_limit = Some(time);
+    let info = match endpoint {
+        "/analyze" => searcher.analyze(&position.board, position.to_move, depth),
+        _ => searcher.search_for(&position.board, position.to_move, depth, None, None),
+    };
+    searcher.time_limit = None;
+
+    match endpoint {
+        "/analyze" => (200, analysis(&position, &info)),
+        _ => (200, played(position, &info)),
+    }
+}
+
+fn analysis(position: &Position, info: &SearchInfo) -> Value {
+    let mut scores: Vec<Value> = info.move_scores.iter()
+        .map(|&(mv, score)| json!({ "move": mv.to_string(), "column": mv.col(), "pop": matches!(mv, Move::Pop(_)), "score": score }))
+        .collect();
+    scores.sort_by_key(|s| (s["pop"].as_bool(), s["column"].as_u64()));
+
+    json!({
+        "to_move": position.to_move.to_string(),
+        "best_move": info.best_move.map(|mv| mv.to_string()),
+        "best_column": info.best_move.map(|mv| mv.col()),
+        "score": info.score,
+        "result": info.score_text(),
+        "scores": scores,
+        "pv": info.pv.iter().map(|mv| mv.to_string()).collect::<Vec<_>>(),
+        "depth": info.depth,
+        "nodes": info.nodes,
+        "time_ms": info.elapsed.as_millis() as u64,
+    })
+}
+
+fn played(mut position: Position, info: &SearchInfo) -> Value {
+    let mv = info.best_move.expect("a position that is not over has a move");
+    let mover = position.to_move;
+    position.board = position.board.play(mv, mover).expect("the search plays legal moves");
+    position.moves.push(mv);
+    position.to_move = position.board.next_player(mover);
+
+    json!({
+        "move": mv.to_string(),
+        "column": mv.col(),
+        "played_by": mover.to_string(),
+        "score": info.score,
+        "depth": info.depth,
+        "time_ms": info.elapsed.as_millis() as u64,
+        "moves": position.moves.iter().map(|mv| mv.to_string()).collect::<Vec<_>>(),
+        "state": game_state(&position),
+    })
+}
+
+// who is to move and whether the game has ended
+fn game_state(position: &Position) -> Value {
+    let board = &position.board;
+    let winner = match position.moves.is_empty() {
+        true => None,
+        false => board.winner(board.previous_player(position.to_move)),
+    };
+    let over = winner.is_some() || !board.has_legal_move(position.to_move);
+
+    json!({
+        "to_move": position.to_move.to_string(),
+        "game_over": over,
+        "winner": winner.map(|w| w.to_string()),
+        "board": board.to_string().trim_matches('\n'),
+    })
+}
+
+// answers requests until the process is stopped
+pub fn serve(server: Server, params: EvalParams) {
+    let mut searcher = Searcher::new(params);
+    let json_header = Header::from_bytes("Content-Type", "application/json").expect("valid header");
+
+    for mut request in server.incoming_requests() {
+        let (status, value) = match read_body(&mut request) {
+            Ok(body) => handle(&mut searcher, &request.method().to_string(), request.url(), &body),
+            Err(error) => error,
+        };
+
+        let response = Response::from_string(value.to_string()).with_status_code(status).with_header(json_header.clone());
+        // a client that went away does not stop the server
+        let _ = request.respond(response);
+    }
+}
+
+// the body of the request, refused when it is larger than MAX_BODY
+fn read_body(request: &mut Request) -> Result<String, (u16, Value)> {
+    let too_large = || (413, json!({ "error": format!("The request body is larger than {} bytes", MAX_BODY) }));
+    if request.body_length().is_some_and(|length| length > MAX_BODY) {
+        return Err(too_large());
+    }
+
+    // a chunked body has no length - reading stops one byte after the limit
+    let mut body = String::new();
+    request.as_reader().take(MAX_BODY as u64 + 1).read_to_string(&mut body)
+        .map_err(|err| (400, json!({ "error": format!("Cannot read the request: {}", err) })))?;
+    match body.len() > MAX_BODY {
+        true => Err(too_large()),
+        false => Ok(body),
+    }
+}
+
+pub fn run(address: &str, params: EvalParams) -> Result<(), String> {
+    let server = Server::http(address).map_err(|err| format!("Cannot listen on {}: {}", address, err))?;
+    println!("Listening on http://{} (POST /analyze, POST /move)", address);
+    serve(server, params);
+    Ok(())
+}
@@ -0,0 +1,245 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use web_time::{SystemTime, UNIX_EPOCH};
+
+use crate::bitboard::{BitBoard, Move, Piece};
+
+// finished games against the AI, one [[game]] table each in a TOML file - new games are appended, so
+// the file is never rewritten
+//
+// the rating is an Elo estimate from the results against the difficulty levels, which are placed on
+// a fixed scale: level 1 at 700, every level 100 points above the one before
+
+const LEVEL_ONE_RATING: f64 = 700.0;
+const LEVEL_STEP: f64 = 100.0;
+// games listed by the `stats` command
+const RECENT_GAMES: usize = 10;
+
+// the file in the home directory, or the one named by CONNECT4_STATS
+pub fn default_path() -> PathBuf {
+    if let Some(path) = std::env::var_os("CONNECT4_STATS") {
+        return PathBuf::from(path);
+    }
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")).unwrap_or_default();
+    PathBuf::from(home).join(".connect-4-stats.toml")
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GameResult {
+    Win,
+    Draw,
+    Loss,
+}
+
+impl GameResult {
+    // points of the human: 1 for a win, a half for a draw
+    pub fn score(self) -> f64 {
+        match self {
+            GameResult::Win => 1.0,
+            GameResult::Draw => 0.5,
+            GameResult::Loss => 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GameRecord {
+    pub rows: u8,
+    pub cols: u8,
+    pub connect: u8,
+    pub variant: String,
+    pub players: u8,
+    pub difficulty: u8,
+    pub result: GameResult,   // for the human
+    pub moves: u32,
+    pub duration_secs: f64,
+    pub finished_at: u64,     // seconds since 1970
+    // what a replay needs on top - games recorded before replays have no moves
+    #[serde(default)]
+    pub misere: bool,
+    #[serde(default)]
+    pub blocked: Vec<(u8, u8)>,
+    #[serde(default = "player_started")]
+    pub player_started: bool,
+    #[serde(default)]
+    pub history: String,      // the moves in order, e.g. "3 3 4 p2"
+}
+
+fn player_started() -> bool {
+    true
+}
+
+impl GameRecord {
+    // `board` is the final position, `first` the colour that made the first move
+    pub fn new(board: &BitBoard, first: Piece, history: &[Move], difficulty: u8, result: GameResult, duration: Duration) -> Self {
+        let history: Vec<String> = history.iter().map(|mv| mv.to_string()).collect();
+        Self {
+            rows: board.rows,
+            cols: board.cols,
+            connect: board.connect,
+            variant: board.variant.name().to_string(),
+            players: board.players,
+            difficulty,
+            result,
+            moves: history.len() as u32,
+            duration_secs: (duration.as_secs_f64() * 10.0).round() / 10.0,
+            finished_at: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs()),
+            misere: board.misere,
+            blocked: board.blocked_cells(),
+            player_started: first == Piece::Player,
+            history: history.join(" "),
+        }
+    }
+
+    // one line for lists of games: "classic 6x7, difficulty 5, win in 21 moves"
+    pub fn summary(&self) -> String {
+        let result = match self.result {
+            GameResult::Win => "win",
+            GameResult::Draw => "draw",
+            GameResult::Loss => "loss",
+        };
+        format!("{} {}x{}, difficulty {}, {} in {} moves", self.variant, self.rows, self.cols, self.difficulty, result, self.moves)
+    }
+}
+
+// results at one difficulty
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LevelSummary {
+    pub difficulty: u8,
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl LevelSummary {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    pub fn win_rate(&self) -> f64 {
+        self.wins as f64 / self.games().max(1) as f64
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Stats {
+    #[serde(default, rename = "game")]
+    pub games: Vec<GameRecord>,
+}
+
+impl Stats {
+    // a missing file is an empty store
+    pub fn load(path: &Path) -> Result<Self, String> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = fs::read_to_string(path).map_err(|err| format!("Cannot read {}: {}", path.display(), err))?;
+        toml::from_str(&text).map_err(|err: toml::de::Error| format!("Invalid statistics file {}: {}", path.display(), err.message()))
+    }
+
+    // adds the game to the store and to the file - the browser has no files, there the games are only
+    // kept until the page is closed
+    pub fn record(&mut self, path: &Path, game: GameRecord) -> Result<(), String> {
+        let saved = match cfg!(target_arch = "wasm32") {
+            true => Ok(()),
+            false => append(path, &game),
+        };
+        self.games.push(game);
+        saved
+    }
+
+    // games against a single AI, the ones the levels and the rating are about
+    fn duels(&self) -> impl Iterator<Item = &GameRecord> {
+        self.games.iter().filter(|game| game.players == 2)
+    }
+
+    pub fn by_difficulty(&self) -> Vec<LevelSummary> {
+        let mut levels: BTreeMap<u8, LevelSummary> = BTreeMap::new();
+        for game in self.duels() {
+            let level = levels.entry(game.difficulty).or_insert_with(|| LevelSummary { difficulty: game.difficulty, ..LevelSummary::default() });
+            match game.result {
+                GameResult::Win => level.wins += 1,
+                GameResult::Draw => level.draws += 1,
+                GameResult::Loss => level.losses += 1,
+            }
+        }
+        levels.into_values().collect()
+    }
+
+    // the rating that makes the expected score of all games equal to the real one (maximum likelihood),
+    // kept within 400 points of the levels played when every game was won or lost
+    pub fn rating(&self) -> Option<f64> {
+        let games: Vec<(f64, f64)> = self.duels().map(|game| (level_rating(game.difficulty), game.result.score())).collect();
+        let lowest = games.iter().map(|&(level, _)| level).reduce(f64::min)?;
+        let highest = games.iter().map(|&(level, _)| level).reduce(f64::max)?;
+
+        // the real minus the expected score falls as the rating rises, the root is found by bisection
+        let surplus = |rating: f64| -> f64 { games.iter().map(|&(level, score)| score - expected_score(rating, level)).sum() };
+        let (mut low, mut high) = (lowest - 400.0, highest + 400.0);
+        for _ in 0..50 {
+            let middle = (low + high) / 2.0;
+            match surplus(middle) > 0.0 {
+                true => low = middle,
+                false => high = middle,
+            }
+        }
+        Some((low + high) / 2.0)
+    }
+
+    // the last `count` games with their numbers, the newest first
+    pub fn recent(&self, count: usize) -> Vec<(usize, &GameRecord)> {
+        self.games.iter().enumerate().rev().take(count).map(|(i, game)| (i + 1, game)).collect()
+    }
+
+    // the `stats` command
+    pub fn report(&self) -> String {
+        if self.games.is_empty() {
+            return "No games played yet.".to_string();
+        }
+        let mut lines = vec![format!("{} games played", self.games.len())];
+
+        let levels = self.by_difficulty();
+        if !levels.is_empty() {
+            lines.push(String::new());
+            lines.push("Difficulty  Games  Wins  Draws  Losses  Win rate".to_string());
+            for level in &levels {
+                lines.push(format!(
+                    "{:>10}  {:>5}  {:>4}  {:>5}  {:>6}  {:>7.0}%",
+                    level.difficulty, level.games(), level.wins, level.draws, level.losses, level.win_rate() * 100.0
+                ));
+            }
+        }
+        if let Some(rating) = self.rating() {
+            lines.push(String::new());
+            lines.push(format!("Estimated rating: {:.0} (difficulty 1 is {:.0}, each level adds {:.0})", rating, LEVEL_ONE_RATING, LEVEL_STEP));
+        }
+
+        lines.push(String::new());
+        lines.push("Last games (replay N to step through one):".to_string());
+        lines.extend(self.recent(RECENT_GAMES).into_iter().map(|(number, game)| format!("  {}. {}", number, game.summary())));
+        lines.join("\n")
+    }
+}
+
+// appends one game to the file, which is created with the first game
+pub fn append(path: &Path, game: &GameRecord) -> Result<(), String> {
+    let text = toml::to_string(&Stats { games: vec![game.clone()] }).expect("records are always serializable");
+    let mut file = OpenOptions::new().create(true).append(true).open(path)
+        .map_err(|err| format!("Cannot write {}: {}", path.display(), err))?;
+    writeln!(file, "{}", text).map_err(|err| format!("Cannot write {}: {}", path.display(), err))
+}
+
+pub fn level_rating(difficulty: u8) -> f64 {
+    LEVEL_ONE_RATING + LEVEL_STEP * (difficulty as f64 - 1.0)
+}
+
+// Elo expectation of a player rated `rating` against `opponent`
+fn expected_score(rating: f64, opponent: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf((opponent - rating) / 400.0))
+}
@@ -1,6 +1,19 @@
+use std::collections::HashMap;
 use std::io;
-use crate::minimax::minimax;
-use crate::bitboard::{BitBoard, Piece};
+use std::net::{IpAddr, Ipv4Addr, TcpListener};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::bitboard::{BitBoard, Move, Piece, PositionKey};
+use crate::cli::Args;
+use crate::clock::{flag_message, flag_result, format_time, Clock};
+use crate::engine::Engine;
+use crate::net::{Connection, GameSetup, NetError, NetGame, NetRole};
+use crate::params::EvalParams;
+use crate::replay::Replay;
+use crate::search::Searcher;
+use crate::stats::{self, GameRecord, GameResult, Stats};
 
 #[derive(PartialEq)]
 pub enum Mode {
@@ -34,19 +47,18 @@ pub fn game_mode_settings_input() -> Mode {
     mode
 }
 
-pub fn get_player_settings_input() -> (u8, u8, u8) {
-    let mut row_count = 6;
-    let mut column_count = 7;
-    let mut win_sequence = 4;
+// asks for the board size, empty input keeps the default of the variant
+pub fn get_player_settings_input(defaults: (u8, u8, u8)) -> (u8, u8, u8) {
+    let (mut row_count, mut column_count, mut win_sequence) = defaults;
 
     // get rows input
     loop {
-        println!("Enter number of rows (2-20, default 6): ");
+        println!("Enter number of rows (2-20, default {}): ", row_count);
         let mut input = String::new();
         io::stdin().read_line(&mut input).unwrap();
 
         if let Ok(n) = input.trim().parse() {
-            if n >= 2 && n <= 20 {
+            if (2..=20).contains(&n) {
                 row_count = n;
                 break;
             }
@@ -59,14 +71,15 @@ pub fn get_player_settings_input() -> (u8, u8, u8) {
     }
 
     // get columns input
-    let max_col = get_conjugate_value(row_count);
+    let max_col = BitBoard::max_cols(row_count);
+    column_count = column_count.min(max_col);
     loop {
-        println!("Enter number of columns (2-{}, default 7): ", {max_col});
+        println!("Enter number of columns (2-{}, default {}): ", max_col, column_count);
         let mut input = String::new();
         io::stdin().read_line(&mut input).unwrap();
 
         if let Ok(n) = input.trim().parse() {
-            if n >= 2 && n <= max_col && n <= max_col {
+            if (2..=max_col).contains(&n) {
                 column_count = n;
                 break;
             }
@@ -80,13 +93,14 @@ pub fn get_player_settings_input() -> (u8, u8, u8) {
 
     // get winning sequence input
     let max_win = row_count.min(column_count);
+    win_sequence = win_sequence.min(max_win);
     loop {
-        println!("Enter the required winning sequence (2-{}, default 4): ", {max_win});
+        println!("Enter the required winning sequence (2-{}, default {}): ", max_win, win_sequence);
         let mut input = String::new();
         io::stdin().read_line(&mut input).unwrap();
 
         if let Ok(n) = input.trim().parse() {
-            if n >= 2 && n <= max_win {
+            if (2..=max_win).contains(&n) {
                 win_sequence = n;
                 break;
             }
@@ -101,19 +115,64 @@ pub fn get_player_settings_input() -> (u8, u8, u8) {
     (row_count, column_count, win_sequence)
 }
 
-pub fn get_player_column_input(size: u8) -> u8 {
-    loop {
-        println!("Enter column number (0-{}): ", {size-1});
+// reads a move - with Pop Out rules "p3" pops the bottom piece of column 3
+pub fn get_player_move_input(board: &BitBoard) -> Move {
+    let read_line = || {
         let mut input = String::new();
         io::stdin().read_line(&mut input).unwrap();
+        Some(input)
+    };
+    read_move(board, read_line).expect("stdin is read until there is a move")
+}
+
+// the same on a clock: the lines come from stdin_lines, and the wait ends when the time for the move is up
+// returns None when the flag fell first
+fn get_player_move_on_clock(board: &BitBoard, lines: &Receiver<String>, clock: &Clock) -> Option<Move> {
+    let read_line = || {
+        let time = clock.time_for_move(Piece::Player)?;
+        match lines.recv_timeout(time) {
+            Ok(line) => Some(line),
+            Err(RecvTimeoutError::Timeout) => None,
+            // stdin was closed, no move can come any more
+            Err(RecvTimeoutError::Disconnected) => {
+                thread::sleep(time);
+                None
+            }
+        }
+    };
+    read_move(board, read_line)
+}
+
+fn read_move(board: &BitBoard, mut read_line: impl FnMut() -> Option<String>) -> Option<Move> {
+    let pops = board.variant.has_pops();
+    loop {
+        match pops {
+            true => println!("Enter column number to drop (0-{}) or p<column> to pop: ", board.cols - 1),
+            false => println!("Enter column number (0-{}): ", board.cols - 1),
+        }
+        let input = read_line()?;
 
-        match input.trim().parse() {
-            Ok(col) if col < size => return col,
+        match input.trim().parse::<Move>() {
+            Ok(mv) if mv.col() < board.cols && (pops || matches!(mv, Move::Drop(_))) => return Some(mv),
+            _ if pops => println!("Invalid move."),
             _ => println!("Invalid column."),
         }
     }
 }
 
+// the lines of stdin, read in a thread so that waiting for them can time out
+fn stdin_lines() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
 pub fn difficulty_input() -> u8 {
     let mut difficulty = 10;
 
@@ -165,66 +224,309 @@ pub fn first_player_input() -> bool {
     player_starts
 }
 
-pub fn main_loop_terminal() {
-    let settings = get_player_settings_input();
+pub fn main_loop_terminal(args: &Args, params: &EvalParams) {
+    println!("{} - {}", args.variant, args.variant.description());
+    if args.misere {
+        println!("Misère: completing a line loses!");
+    }
+    let settings = get_player_settings_input(args.variant.default_size());
     let difficulty = difficulty_input(); // user inputs difficulty
     let player_starts = first_player_input();
-    let mut board = BitBoard::new(settings.0, settings.1, settings.2);
+    let mut tie_break = args.tie_break();
+    let mut engine = Engine::new(Searcher::new(params.clone()), args.ponder);
+    let mut board = BitBoard::new(settings.0, settings.1, settings.2).with_variant(args.variant)
+        .with_players(args.players)
+        .with_misere(args.misere)
+        .with_first(if player_starts { Piece::Player } else { Piece::AI });
+    board = args.place_blockers(board);
+    if args.blocker_count > 0 {
+        println!("Random blocked cells (#): seed {}, rerun with --blocker-seed {} to get the same cells", args.blocker_seed, args.blocker_seed);
+    }
+    if board.players > 2 {
+        let symbols = ["O", "+", "*"];
+        println!("You play X against {} AI colours ({}){}", board.players - 1, symbols[..board.players as usize - 1].join(", "),
+            if board.variant.has_teams() { " - teams: X and + against O and *" } else { "" });
+    }
+    let mut clock = Clock::new(args.time_control);
+    if clock.control.is_timed() {
+        println!("Clock: {}", clock.control);
+    }
+    // on a clock the flag can fall while you think, the input is read with a timeout
+    let lines = clock.control.is_timed().then(stdin_lines);
     println!("{}", board);
 
-    let mut current = match player_starts {
-        true => Piece::Player,
-        false => Piece::AI,
-    };
+    let mut current = board.first;
+    // with pops positions can come back - the third time it is a draw
+    let mut seen: HashMap<(PositionKey, Piece), u8> = HashMap::new();
 
-    loop {
-        let col: u8;
+    let started = Instant::now();
+    let first = current;
+    let mut history = Vec::new();
+    let result = loop {
+        let mv: Move;
+        clock.start(current);
         if current == Piece::Player {
-            col = get_player_column_input(settings.1);
+            engine.start_pondering(&board, difficulty);
+            let input = match &lines {
+                Some(lines) => get_player_move_on_clock(&board, lines, &clock),
+                None => Some(get_player_move_input(&board)),
+            };
+            engine.stop_pondering();
+            mv = match input {
+                Some(mv) => mv,
+                None => {
+                    println!("{}", flag_message(&board, current));
+                    break Some(GameResult::Loss);
+                }
+            };
+            if engine.pondering_enabled {
+                println!("(pondered to depth {})", engine.last_ponder_depth);
+            }
         } else {
             // AI move using minimax
-            println!("AI is thinking...");
-            col = match minimax(&board, difficulty, i32::MIN, i32::MAX, true).0 {
-                Some(c) => c,
+            println!("{} is thinking...", current);
+            let searcher = engine.searcher();
+            searcher.time_limit = clock.think_time(&board, current);
+            let info = searcher.search_for(&board, current, difficulty, tie_break.as_mut(), None);
+            searcher.time_limit = None;
+            mv = match info.best_move {
+                Some(m) => m,
                 None => {
-                    println!("No valid moves for AI!");
-                    break;
+                    println!("No valid moves for {}!", current);
+                    break None;
                 }
             }; // adjust depth if needed
-            println!("AI chooses column: {}", col);
+            match mv {
+                Move::Drop(col) => println!("{} chooses column: {}", current, col),
+                Move::Pop(col) => println!("{} pops column: {}", current, col),
+            }
+            println!("({})", info);
         }
 
         // Try to apply move
-        if let Some(new_board) = board.drop_piece(col, current) {
+        if let Some(new_board) = board.play(mv, current) {
+            // the move only counts if it came in time
+            if !clock.stop() {
+                println!("{}", flag_message(&board, current));
+                break Some(flag_result(&board, current));
+            }
             board = new_board;
+            history.push(mv);
             println!("{}", board);
+            print_clocks(&clock, &board);
 
-            if board.check_win(current) {
-                println!("{:?} wins!", current);
-                break;
+            if let Some(winner) = board.winner(current) {
+                match board.variant.has_teams() {
+                    true if board.same_team(winner, Piece::Player) => println!("{} connected - your team wins!", winner),
+                    true => println!("{} connected - the other team wins!", winner),
+                    false => println!("{} wins!", winner),
+                }
+                break Some(if board.same_team(winner, Piece::Player) { GameResult::Win } else { GameResult::Loss });
             }
 
-            if board.is_full() {
+            // Switch player
+            current = board.next_player(current);
+
+            if !board.has_legal_move(current) {
                 println!("It's a draw!");
-                break;
+                break Some(GameResult::Draw);
             }
 
-            // Switch player
-            current = if current == Piece::Player { Piece::AI } else { Piece::Player };
+            let count = seen.entry((board.position_key(), current)).or_insert(0);
+            *count += 1;
+            if *count >= 3 {
+                println!("Same position three times - it's a draw!");
+                break Some(GameResult::Draw);
+            }
         } else {
-            println!("Column {} is full. Try again.", col);
+            match mv {
+                Move::Drop(_) if board.pop_phase() => println!("The board was filled, only pops are allowed now. Try again."),
+                Move::Drop(col) => println!("Column {} is full. Try again.", col),
+                Move::Pop(col) => println!("Cannot pop column {} - the bottom piece must be yours. Try again.", col),
+            }
+        }
+    };
+
+    if let Some(result) = result {
+        let game = GameRecord::new(&board, first, &history, difficulty, result, started.elapsed());
+        if let Err(err) = stats::append(&args.stats_file, &game) {
+            println!("The game was not recorded: {}", err);
         }
     }
 }
 
-const MAX_DIFFICULTY: u8 = 20;
-const MIN_DIFFICULTY: u8 = 1;
+// steps through a recorded game - a letter and Enter for each step, as the terminal reads whole lines
+pub fn replay_terminal(args: &Args, params: &EvalParams, number: Option<usize>) {
+    let replay = Stats::load(&args.stats_file).and_then(|stats| Replay::from_stats(&stats, number));
+    let mut replay = match replay {
+        Ok(replay) => replay,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+    let mut searcher = Searcher::new(params.clone());
+    let mut evaluate = false;
+    println!("Replay: {}", replay.record.summary());
 
-fn is_valid_difficulty(difficulty: u8) -> bool {
-    difficulty >= MIN_DIFFICULTY && difficulty <= MAX_DIFFICULTY
+    loop {
+        println!("{}", replay.describe());
+        println!("{}", replay.board());
+        if evaluate {
+            match replay.evaluate(&mut searcher, replay.record.difficulty.max(1)) {
+                Some(info) => println!("Engine, {} to move: {}", replay.to_move(), info),
+                None => println!("Engine: the game is over"),
+            }
+        }
+
+        println!("[Enter/n] next, [p] previous, [s] start, [e] end, [<N>] go to move N, [v] evaluation on/off, [q] quit: ");
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input).unwrap_or(0) == 0 {
+            return;
+        }
+        match input.trim() {
+            "" | "n" => replay.forward(),
+            "p" => replay.back(),
+            "s" => replay.go_to(0),
+            "e" => replay.go_to(replay.len()),
+            "v" => evaluate = !evaluate,
+            "q" => return,
+            other => match other.parse() {
+                Ok(position) => replay.go_to(position),
+                Err(_) => println!("Unknown command: {}", other),
+            },
+        }
+    }
+}
+
+// the time left of every colour, for games on a clock
+fn print_clocks(clock: &Clock, board: &BitBoard) {
+    let times: Vec<String> = Piece::COLOURS[..board.players as usize].iter()
+        .filter_map(|&piece| clock.remaining(piece).map(|time| format!("{} {}", piece, format_time(time))))
+        .collect();
+    if !times.is_empty() {
+        println!("Clock - {}", times.join(", "));
+    }
+}
+
+// a game against a person over TCP - the host picks the board and rules, the guest gets them
+pub fn main_loop_network(args: &Args, role: &NetRole) {
+    let started = match role {
+        NetRole::Host(port) => host_network_game(args, *port),
+        NetRole::Join(address) => {
+            println!("Connecting to {}...", address);
+            Connection::join(address.as_str()).map(|(connection, game)| (connection, game, None))
+        }
+    };
+    let (mut connection, mut game, listener) = match started {
+        Ok(started) => started,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+
+    let me = connection.me;
+    println!("{} - you play {}", game.setup.variant, if me == Piece::Player { "X" } else { "O" });
+    println!("{}", game.board);
+
+    loop {
+        if game.is_over() {
+            match game.winner() {
+                Some(winner) if winner == me => println!("You win!"),
+                Some(_) => println!("Your opponent wins!"),
+                None => println!("It's a draw!"),
+            }
+            connection.close();
+            break;
+        }
+
+        let result = if game.to_move == me {
+            loop {
+                match game.play(get_player_move_input(&game.board)) {
+                    Ok(()) => break,
+                    Err(err) => println!("{}. Try again.", err),
+                }
+            }
+            connection.send_move(&game)
+        } else {
+            println!("Waiting for your opponent...");
+            connection.receive_move(&mut game)
+        };
+
+        match result {
+            Ok(()) => println!("{}", game.board),
+            Err(NetError::Protocol(text)) => {
+                println!("{}", text);
+                break;
+            }
+            // the connection was lost - the guest connects again and gets the host's game
+            Err(err) => {
+                println!("{} - trying to resume the game...", err);
+                match resume_network_game(role, listener.as_ref(), &game) {
+                    Some((resumed, synced)) => {
+                        connection = resumed;
+                        game = synced;
+                        println!("{}", game.board);
+                    }
+                    None => {
+                        println!("Could not resume the game.");
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn host_network_game(args: &Args, port: u16) -> Result<(Connection, NetGame, Option<TcpListener>), NetError> {
+    let address = args.bind.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    let listener = TcpListener::bind((address, port))?;
+
+    println!("{} - {}", args.variant, args.variant.description());
+    let (rows, cols, connect) = get_player_settings_input(args.variant.default_size());
+    let host_starts = first_player_input();
+    let board = args.place_blockers(BitBoard::new(rows, cols, connect).with_variant(args.variant).with_misere(args.misere));
+    let setup = GameSetup { rows, cols, connect, variant: args.variant, misere: board.misere, blocked: board.blocked_cells(), host_starts };
+    let game = NetGame::new(setup);
+
+    println!("Waiting for an opponent on port {}...", port);
+    let connection = Connection::accept(&listener, &game)?;
+    Ok((connection, game, Some(listener)))
 }
 
-// board area must be < 128 so that it fits in one number (with paddings between columns)
-fn get_conjugate_value(x: u8) -> u8 {
-    (128/(x+1))-1
+// how long both sides try to get the game back after the connection was lost
+const RESUME_TIME: Duration = Duration::from_secs(30);
+
+fn resume_network_game(role: &NetRole, listener: Option<&TcpListener>, game: &NetGame) -> Option<(Connection, NetGame)> {
+    let deadline = Instant::now() + RESUME_TIME;
+    match (role, listener) {
+        (NetRole::Host(_), Some(listener)) => {
+            println!("Waiting for your opponent to reconnect...");
+            Connection::accept_again(listener, game, deadline).ok().map(|c| (c, game.clone()))
+        }
+        (NetRole::Join(address), _) => {
+            while Instant::now() < deadline {
+                thread::sleep(Duration::from_secs(1));
+                match Connection::join_again(address.as_str(), game) {
+                    Ok(resumed) => return Some(resumed),
+                    // the host refused us or plays something else - trying again will not help
+                    Err(NetError::Protocol(text)) => {
+                        println!("{}", text);
+                        return None;
+                    }
+                    Err(NetError::Io(_)) => {}
+                }
+            }
+            None
+        }
+        _ => None,
+    }
+}
+
+pub const MAX_DIFFICULTY: u8 = 20;
+pub const MIN_DIFFICULTY: u8 = 1;
+
+fn is_valid_difficulty(difficulty: u8) -> bool {
+    (MIN_DIFFICULTY..=MAX_DIFFICULTY).contains(&difficulty)
 }
\ No newline at end of file
@@ -0,0 +1,198 @@
+use std::path::PathBuf;
+use std::thread;
+
+use crate::bitboard::{BitBoard, Move, Piece};
+use crate::minimax::best_move;
+use crate::params::{EvalParams, MAX_WEIGHT};
+use crate::rng::Rng;
+
+// self-play games are played on the standard board
+const ROWS: u8 = 6;
+const COLS: u8 = 7;
+const CONNECT: u8 = 4;
+
+// SPSA gain schedules (Spall's recommended exponents)
+const ALPHA: f64 = 0.602;
+const GAMMA: f64 = 0.101;
+
+// settings of a tuning run
+#[derive(Clone, Debug)]
+pub struct TuneConfig {
+    pub output: PathBuf,
+    pub iterations: u32,
+    pub games: u32,         // game pairs (both colours) per iteration
+    pub depth: u8,          // search depth of both sides
+    pub opening_moves: u8,  // random moves before the engines take over, for variety
+    pub step: f64,          // relative perturbation of the parameters (in log space, 0.5 = about +-65%)
+    pub learning_rate: f64,
+    pub seed: u64,
+}
+
+impl Default for TuneConfig {
+    fn default() -> Self {
+        Self {
+            output: PathBuf::from("eval.toml"),
+            iterations: 100,
+            games: 8,
+            depth: 4,
+            opening_moves: 4,
+            step: 0.5,
+            learning_rate: 0.5,
+            seed: 0,
+        }
+    }
+}
+
+// tunes the evaluation weights with SPSA (simultaneous perturbation stochastic approximation)
+// every iteration plays two randomly perturbed parameter sets against each other
+// and moves the parameters towards the winner - the result is written after each iteration
+pub fn tune(start: EvalParams, config: &TuneConfig) -> Result<EvalParams, String> {
+    let mut rng = Rng::new(config.seed);
+
+    // parameters are tuned in log space: perturbations are relative and values stay positive
+    let mut theta: Vec<f64> = to_vector(&start).iter().map(|v| v.max(1.0).ln()).collect();
+
+    println!(
+        "Tuning {} parameters: {} iterations, {} game pairs each, depth {}, seed {}",
+        theta.len(), config.iterations, config.games, config.depth, config.seed
+    );
+
+    for k in 0..config.iterations {
+        let c_k = config.step / (k as f64 + 1.0).powf(GAMMA);
+        let a_k = config.learning_rate / (k as f64 + 1.0 + config.iterations as f64 / 10.0).powf(ALPHA);
+
+        let delta: Vec<f64> = theta.iter().map(|_| if rng.below(2) == 0 { -1.0 } else { 1.0 }).collect();
+        let plus: Vec<f64> = theta.iter().zip(&delta).map(|(t, d)| t + c_k * d).collect();
+        let minus: Vec<f64> = theta.iter().zip(&delta).map(|(t, d)| t - c_k * d).collect();
+
+        let plus_params = from_vector(&start, &plus);
+        let minus_params = from_vector(&start, &minus);
+        let score = play_match(&plus_params, &minus_params, config, &mut rng);
+
+        // gradient estimate: (f(plus) - f(minus)) / (2 c_k delta), with score = f(plus) - f(minus)
+        for (t, d) in theta.iter_mut().zip(&delta) {
+            *t += a_k * score / (2.0 * c_k * d);
+        }
+
+        let current = from_vector(&start, &theta);
+        current.save(&config.output)?;
+        println!("iteration {}/{}: score {:+.2} -> {:?}", k + 1, config.iterations, score, to_vector(&current));
+    }
+
+    let tuned = from_vector(&start, &theta);
+    let score = play_match(&tuned, &start, config, &mut rng);
+    println!("Tuned vs starting parameters: {:+.2} (1 = all won, -1 = all lost)", score);
+    println!("Parameters written to {}", config.output.display());
+
+    Ok(tuned)
+}
+
+// plays `games` pairs from random openings (each opening with both colours)
+// returns the average result from the view of `a` in [-1, 1]
+fn play_match(a: &EvalParams, b: &EvalParams, config: &TuneConfig, rng: &mut Rng) -> f64 {
+    let openings: Vec<Vec<u8>> = (0..config.games)
+        .map(|_| random_opening(config.opening_moves, rng))
+        .collect();
+
+    // every opening is played on its own thread
+    let total: i32 = thread::scope(|scope| {
+        let handles: Vec<_> = openings.iter()
+            .map(|opening| scope.spawn(move || {
+                play_game(opening, a, b, config.depth) - play_game(opening, b, a, config.depth)
+            }))
+            .collect();
+
+        handles.into_iter().map(|h| h.join().unwrap()).sum()
+    });
+
+    total as f64 / (2 * config.games) as f64
+}
+
+fn random_opening(moves: u8, rng: &mut Rng) -> Vec<u8> {
+    (0..moves).map(|_| rng.below(COLS as usize) as u8).collect()
+}
+
+// plays one game, returns 1 if `first` wins, -1 if `second` wins and 0 for a draw
+// the board is kept from the view of the side to move (AI = to move) and swapped after every move
+fn play_game(opening: &[u8], first: &EvalParams, second: &EvalParams, depth: u8) -> i32 {
+    let mut board = BitBoard::new(ROWS, COLS, CONNECT).with_first(Piece::AI);
+    let mut first_to_move = true;
+    let mut moves = opening.iter();
+
+    loop {
+        let mv = match moves.next() {
+            Some(&col) if board.is_valid_location(col) => Some(Move::Drop(col)),
+            Some(_) => continue,
+            None => {
+                let params = if first_to_move { first } else { second };
+                best_move(&board, depth, params, None).0
+            }
+        };
+
+        let mv = match mv {
+            Some(mv) => mv,
+            None => return 0,
+        };
+
+        board = board.play(mv, Piece::AI).unwrap();
+
+        if board.check_win(Piece::AI) {
+            return if first_to_move { 1 } else { -1 };
+        }
+        if board.is_full() {
+            return 0;
+        }
+
+        board = board.swapped();
+        first_to_move = !first_to_move;
+    }
+}
+
+// the tuned parameters - win_score is left out, searched positions never contain a win,
+// and kept_piece only matters in Pop 10 (self-play uses the classic rules)
+fn to_vector(params: &EvalParams) -> Vec<f64> {
+    vec![
+        params.double_threat_bonus as f64,
+        params.weight_missing_one as f64,
+        params.weight_missing_two as f64,
+        params.weight_shorter as f64,
+        params.center_weight as f64,
+        params.immediate_threat as f64,
+        params.threat_good_parity as f64,
+        params.threat_bad_parity as f64,
+    ]
+}
+
+// the weights stay within MAX_WEIGHT, larger ones could make the evaluation overflow or look like a win
+fn from_vector(base: &EvalParams, theta: &[f64]) -> EvalParams {
+    let value = |i: usize| theta[i].exp().round().clamp(1.0, MAX_WEIGHT as f64) as i32;
+
+    EvalParams {
+        double_threat_bonus: value(0),
+        weight_missing_one: value(1),
+        weight_missing_two: value(2),
+        weight_shorter: value(3),
+        center_weight: value(4),
+        immediate_threat: value(5),
+        threat_good_parity: value(6),
+        threat_bad_parity: value(7),
+        ..base.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tuned_weights_stay_within_the_bound() {
+        let base = EvalParams::default();
+        let params = from_vector(&base, &[50.0; 8]);
+        assert_eq!(to_vector(&params), vec![MAX_WEIGHT as f64; 8]);
+        // what the tuner writes can be loaded again
+        assert_eq!(EvalParams::from_toml(&params.to_toml()), Ok(params));
+
+        let params = from_vector(&base, &[-50.0; 8]);
+        assert_eq!(to_vector(&params), vec![1.0; 8]);
+    }
+}
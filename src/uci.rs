@@ -0,0 +1,275 @@
+use std::io::{BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::bitboard::{BitBoard, Move, Piece};
+use crate::params::EvalParams;
+use crate::search::{SearchInfo, Searcher};
+use crate::variant::Variant;
+
+// text protocol for other programs (`connect-4 --engine`), one command per line, modelled on UCI:
+//   uci                               -> id name, the options, uciok
+//   isready                           -> readyok
+//   ucinewgame                        forgets the previous game (clears the table)
+//   setoption name <N> value <V>      Rows, Cols, Connect, Variant, Misere, Players or an evaluation weight
+//   position [startpos] [moves] ...   moves from the empty board, "3" drops into column 3, "p3" pops
+//                                     (with an illegal move the previous position stays)
+//   go depth <N> | movetime <MS> | infinite
+//                                     searches the side to move: an info line per depth, then bestmove
+//   stop                              ends the search early, bestmove is still sent
+//   d                                 prints the board
+//   quit
+// errors are reported as "info string ..." lines, the engine keeps going
+
+// depth of a plain "go" - "go movetime" and "go infinite" search deeper until the clock or "stop" ends them
+const DEFAULT_DEPTH: u8 = 10;
+const MAX_DEPTH: u8 = u8::MAX;
+
+struct RunningSearch {
+    handle: JoinHandle<Searcher>,
+    stop: Arc<AtomicBool>,
+}
+
+pub struct EngineProtocol<W: Write + Send + 'static> {
+    out: Arc<Mutex<W>>,
+    rows: u8,
+    cols: u8,
+    connect: u8,
+    variant: Variant,
+    misere: bool,
+    players: u8,
+    board: BitBoard,
+    to_move: Piece,
+    searcher: Option<Searcher>,
+    running: Option<RunningSearch>,
+}
+
+impl<W: Write + Send + 'static> EngineProtocol<W> {
+    pub fn new(output: W, params: EvalParams) -> Self {
+        let (rows, cols, connect) = Variant::Classic.default_size();
+        let mut protocol = Self {
+            out: Arc::new(Mutex::new(output)),
+            rows,
+            cols,
+            connect,
+            variant: Variant::Classic,
+            misere: false,
+            players: 2,
+            board: BitBoard::new(rows, cols, connect),
+            to_move: Piece::Player,
+            searcher: Some(Searcher::new(params)),
+            running: None,
+        };
+        protocol.reset_position();
+        protocol
+    }
+
+    // handles one command line, returns false after "quit"
+    pub fn handle(&mut self, line: &str) -> bool {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, rest) = match words.split_first() {
+            Some((command, rest)) => (*command, rest),
+            None => return true,
+        };
+
+        match command {
+            "uci" => self.identify(),
+            "isready" => self.send("readyok"),
+            "ucinewgame" => {
+                self.stop_search();
+                self.searcher().clear();
+                self.reset_position();
+            }
+            "setoption" => {
+                self.stop_search();
+                if let Err(err) = self.set_option(rest) {
+                    self.send(&format!("info string {}", err));
+                }
+            }
+            "position" => {
+                self.stop_search();
+                if let Err(err) = self.set_position(rest) {
+                    self.send(&format!("info string {}", err));
+                }
+            }
+            "go" => match parse_go(rest) {
+                Ok((depth, time_limit)) => self.go(depth, time_limit),
+                Err(err) => self.send(&format!("info string {}", err)),
+            },
+            "stop" => self.stop_search(),
+            "d" => {
+                let text = format!("{}{} to move", self.board, self.to_move);
+                self.send(&text);
+            }
+            "quit" => {
+                self.stop_search();
+                return false;
+            }
+            _ => self.send(&format!("info string Unknown command: {}", command)),
+        }
+        true
+    }
+
+    // waits for a running search to finish on its own (at the end of the input)
+    pub fn finish(&mut self) {
+        if let Some(running) = self.running.take() {
+            self.searcher = Some(running.handle.join().expect("search thread panicked"));
+        }
+    }
+
+    fn send(&self, line: &str) {
+        send(&self.out, line);
+    }
+
+    fn identify(&self) {
+        self.send(&format!("id name connect-4 {}", env!("CARGO_PKG_VERSION")));
+        self.send("option name Rows type spin default 6 min 2 max 20");
+        self.send("option name Cols type spin default 7 min 2 max 41");
+        self.send("option name Connect type spin default 4 min 2 max 20");
+        let names: Vec<&str> = Variant::ALL.iter().map(|v| v.name()).collect();
+        self.send(&format!("option name Variant type combo default classic var {}", names.join(" var ")));
+        self.send("option name Misere type check default false");
+        self.send("option name Players type spin default 2 min 2 max 4");
+        self.send("uciok");
+    }
+
+    // the searcher once the running search is done
+    fn searcher(&mut self) -> &mut Searcher {
+        self.finish();
+        self.searcher.as_mut().expect("searcher is back after the search")
+    }
+
+    fn stop_search(&mut self) {
+        if let Some(running) = &self.running {
+            running.stop.store(true, Ordering::Relaxed);
+        }
+        self.finish();
+    }
+
+    fn reset_position(&mut self) {
+        self.board = self.start_position();
+        self.to_move = Piece::Player;
+    }
+
+    fn start_position(&self) -> BitBoard {
+        BitBoard::new(self.rows, self.cols, self.connect)
+            .with_variant(self.variant)
+            .with_players(self.players)
+            .with_misere(self.misere)
+    }
+
+    // board options keep the other sizes valid (fewer rows can mean fewer columns), and reset the position
+    fn set_option(&mut self, words: &[&str]) -> Result<(), String> {
+        let value_at = words.iter().position(|&w| w == "value");
+        let name = words.get(1..value_at.unwrap_or(words.len())).unwrap_or_default().join(" ");
+        let value = value_at.map(|i| words[i + 1..].join(" ")).unwrap_or_default();
+        if words.first() != Some(&"name") || name.is_empty() {
+            return Err("Expected setoption name <name> value <value>".to_string());
+        }
+        let number = |min: u8, max: u8| -> Result<u8, String> {
+            value.parse().ok().filter(|n| (min..=max).contains(n))
+                .ok_or_else(|| format!("{} must be between {} and {}", name, min, max))
+        };
+
+        match name.to_lowercase().as_str() {
+            "rows" => {
+                self.rows = number(2, 20)?;
+                self.cols = self.cols.min(BitBoard::max_cols(self.rows));
+            }
+            "cols" => self.cols = number(2, BitBoard::max_cols(self.rows))?,
+            "connect" => self.connect = number(2, self.rows.min(self.cols))?,
+            "variant" => {
+                self.variant = value.parse()?;
+                self.players = self.players.clamp(*self.variant.players().start(), *self.variant.players().end());
+            }
+            "misere" => self.misere = value == "true",
+            "players" => {
+                let allowed = self.variant.players();
+                self.players = number(*allowed.start(), *allowed.end())?;
+            }
+            _ => self.searcher().params.set(&name, &value)?,
+        }
+
+        self.connect = self.connect.min(self.rows.min(self.cols));
+        self.reset_position();
+        Ok(())
+    }
+
+    // the position after the moves - nothing changes if one of them is illegal
+    fn set_position(&mut self, words: &[&str]) -> Result<(), String> {
+        let (mut board, mut to_move) = (self.start_position(), Piece::Player);
+
+        for word in words.iter().filter(|&&w| w != "startpos" && w != "moves") {
+            let mv: Move = word.parse()?;
+            if board.winner(board.previous_player(to_move)).is_some() {
+                return Err(format!("Move {} after the end of the game", mv));
+            }
+            board = board.play(mv, to_move).ok_or_else(|| format!("Illegal move {} for {}", mv, to_move))?;
+            to_move = board.next_player(to_move);
+        }
+
+        self.board = board;
+        self.to_move = to_move;
+        Ok(())
+    }
+
+    // searches in a thread so that "stop" can be read meanwhile
+    fn go(&mut self, depth: u8, time_limit: Option<Duration>) {
+        self.stop_search();
+        let mut searcher = self.searcher.take().expect("no search is running");
+        let stop = searcher.stop_flag();
+        stop.store(false, Ordering::Relaxed);
+        searcher.time_limit = time_limit;
+
+        let (board, to_move, out) = (self.board.clone(), self.to_move, self.out.clone());
+        let handle = thread::spawn(move || {
+            let info = searcher.search_for(&board, to_move, depth, None, Some(&mut |info: &SearchInfo| send(&out, &info_line(info))));
+            send(&out, &format!("bestmove {}", info.best_move.map_or("none".to_string(), |mv| mv.to_string())));
+            searcher
+        });
+
+        self.running = Some(RunningSearch { handle, stop });
+    }
+}
+
+// reads commands until "quit" or the end of the input (a search started last is finished first)
+pub fn run<R: BufRead, W: Write + Send + 'static>(input: R, output: W, params: EvalParams) {
+    let mut protocol = EngineProtocol::new(output, params);
+    for line in input.lines() {
+        match line {
+            Ok(line) if protocol.handle(&line) => {}
+            _ => return,
+        }
+    }
+    protocol.finish();
+}
+
+fn send<W: Write>(out: &Mutex<W>, line: &str) {
+    let mut out = out.lock().expect("output lock");
+    // a closed output only means nobody listens any more
+    let _ = writeln!(out, "{}", line);
+    let _ = out.flush();
+}
+
+fn info_line(info: &SearchInfo) -> String {
+    let pv: Vec<String> = info.pv.iter().map(|mv| mv.to_string()).collect();
+    format!(
+        "info depth {} score {} nodes {} time {} pv {}",
+        info.depth, info.score_text(), info.nodes, info.elapsed.as_millis(), pv.join(" ")
+    )
+}
+
+// depth and time limit of a "go" command
+fn parse_go(words: &[&str]) -> Result<(u8, Option<Duration>), String> {
+    match words {
+        [] => Ok((DEFAULT_DEPTH, None)),
+        ["infinite"] => Ok((MAX_DEPTH, None)),
+        ["depth", n] => n.parse().ok().filter(|&d| d > 0).map(|d| (d, None))
+            .ok_or_else(|| format!("Invalid depth: {}", n)),
+        ["movetime", ms] => ms.parse().map(|ms| (MAX_DEPTH, Some(Duration::from_millis(ms))))
+            .map_err(|_| format!("Invalid move time: {}", ms)),
+        _ => Err(format!("Expected go depth <N>, go movetime <MS> or go infinite, got go {}", words.join(" "))),
+    }
+}
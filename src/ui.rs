@@ -1,49 +1,904 @@
-use crate::minimax::minimax;
-use crate::bitboard::{BitBoard, Piece};
+use crate::minimax::TieBreak;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use web_time::Instant;
+
+use crate::bitboard::{BitBoard, Move, Piece, PositionKey};
+use crate::cli::Args;
+use crate::clock::{flag_message, flag_result, format_time, Clock, TimeControl};
+use crate::params::EvalParams;
+use crate::engine::{Engine, SteppedSearch};
+use crate::search::{SearchInfo, Searcher};
+use crate::replay::Replay;
+use crate::stats::{level_rating, GameRecord, GameResult, Stats};
 use eframe::egui;
 use eframe::egui::{Visuals};
-use crate::terminal::{first_player_input, get_player_settings_input, difficulty_input};
+use crate::terminal::{MAX_DIFFICULTY, MIN_DIFFICULTY};
+use crate::rng::random_seed;
+use crate::variant::Variant;
 
 const CELL_SIZE: f32 = 50.0;
 const CELL_SPACING: f32 = 5.0;
+// games listed in the statistics panel
+const RECENT_GAMES: usize = 5;
+// seconds a dropped piece takes to fall into its cell
+const DROP_TIME: f32 = 0.4;
+// thinking time of a hint
+const HINT_TIME: Duration = Duration::from_secs(1);
+const DIGIT_KEYS: [egui::Key; 10] = [
+    egui::Key::Num0, egui::Key::Num1, egui::Key::Num2, egui::Key::Num3, egui::Key::Num4,
+    egui::Key::Num5, egui::Key::Num6, egui::Key::Num7, egui::Key::Num8, egui::Key::Num9,
+];
+// the keyboard controls, as the help overlay lists them
+const KEYS: [(&str, &str); 11] = [
+    ("0 - 9", "drop into that column"),
+    ("Left / Right", "move the column cursor"),
+    ("Enter or Space", "drop into the cursor column"),
+    ("P", "pop the bottom piece of the cursor column"),
+    ("U or Ctrl+Z", "take back your last move (not on a clock)"),
+    ("H", "hint"),
+    ("N", "new game"),
+    ("Enter", "start the game on the settings screen"),
+    ("Q", "quit"),
+    ("? or F1", "show or hide this help"),
+    ("Escape", "close a window"),
+];
+
+// the engine view of the position shown in a replay, searched a step per frame
+struct ReplayEval {
+    position: usize,
+    search: Option<SteppedSearch>,  // None once it is done
+    info: Option<SearchInfo>,       // None while searching, and when the game is over
+}
+
+// the last dropped piece, drawn falling until it reaches its cell - the clock of the next colour
+// starts once it has landed
+struct DropAnimation {
+    id: egui::Id,
+    row: u8,
+    col: u8,
+    started: bool,  // the first frame places it at the top, the fall starts with the next one
+}
+
+// settings of the next game, edited on the settings screen
+struct GameSettings {
+    variant: Variant,
+    rows: u8,
+    cols: u8,
+    connect: u8,
+    players: u8,
+    misere: bool,
+    blockers: u8,               // random blocked cells
+    blocker_seed: u64,
+    blocker_cells: Vec<(u8, u8)>, // fixed blocked cells from the command line
+    difficulty: u8,
+    player_starts: bool,
+    clock_minutes: f64,         // 0 is no clock
+    increment_secs: f64,
+    move_limit_secs: f64,       // 0 is no limit
+}
+
+impl GameSettings {
+    fn new(variant: Variant) -> Self {
+        let (rows, cols, connect) = variant.default_size();
+        let players = *variant.players().start();
+        Self {
+            variant, rows, cols, connect, players,
+            misere: false,
+            blockers: 0,
+            blocker_seed: random_seed(),
+            blocker_cells: Vec::new(),
+            difficulty: 10,
+            player_starts: true,
+            clock_minutes: 0.0,
+            increment_secs: 0.0,
+            move_limit_secs: 0.0,
+        }
+    }
+
+    fn time_control(&self) -> TimeControl {
+        TimeControl {
+            base: (self.clock_minutes > 0.0).then(|| Duration::from_secs_f64(self.clock_minutes * 60.0)),
+            increment: Duration::from_secs_f64(self.increment_secs),
+            per_move: (self.move_limit_secs > 0.0).then(|| Duration::from_secs_f64(self.move_limit_secs)),
+        }
+    }
+}
 
 pub struct Connect4App {
+    settings: GameSettings,
+    in_game: bool, // false while the settings screen is shown
     game_state: BitBoard,
     difficulty: u8,
     current: Piece,
     game_over: bool,
     message: String,
     ai_move_queued: bool, // Changed from pending_ai_move to ai_move_queued
+    tie_break: Option<TieBreak>,
+    engine: Engine,
+    ai_search: Option<SteppedSearch>, // AI move being searched, a step per frame
+    clock: Clock,
+    started: Instant,   // start of the game, for the statistics
+    game_time: Duration,    // length of the finished game
+    history: Vec<Move>,
+    positions: Vec<(BitBoard, Piece)>,  // board and colour to move before each move of the history, for undo
+    cursor: u8,     // column of the keyboard, follows the mouse
+    hint_search: Option<SteppedSearch>,
+    hint: Option<Move>,
+    show_help: bool,
+    winning_line: Vec<(u8, u8)>,    // cells of the line that ended the game
+    falling: Option<DropAnimation>,
+    drops: u64,     // pieces dropped since the start, each fall is animated under its own id
+    game_over_window: bool,         // the result with rematch and review, until it is closed
+    replay: Option<Replay>,     // shown instead of the game while set
+    replay_eval: Option<ReplayEval>,  // engine view of a replay position, None when off
+    stats: Stats,
+    stats_file: PathBuf,
+    search_progress: Vec<SearchInfo>, // one entry per finished depth of the last AI search
+    seen: HashMap<(PositionKey, Piece), u8>, // position counts - with pops the third repetition is a draw
 }
 
 impl Connect4App {
-    pub fn default(cc: &eframe::CreationContext<'_>) -> Self {
+    pub fn default(cc: &eframe::CreationContext<'_>, args: &Args, params: EvalParams) -> Self {
         cc.egui_ctx.set_visuals(egui::Visuals::dark());
 
-        let settings = get_player_settings_input();
-        let difficulty = difficulty_input();
-        let board = BitBoard::new(settings.0, settings.1, settings.2);
-        let player_starts = first_player_input();
-        let current = match player_starts {
-            true => Piece::Player,
-            false => Piece::AI,
+        let settings = GameSettings {
+            players: args.players.max(2),
+            misere: args.misere,
+            blockers: args.blocker_count,
+            blocker_seed: args.blocker_seed,
+            blocker_cells: args.blocker_cells.clone(),
+            clock_minutes: args.time_control.base.map_or(0.0, |base| base.as_secs_f64() / 60.0),
+            increment_secs: args.time_control.increment.as_secs_f64(),
+            move_limit_secs: args.time_control.per_move.map_or(0.0, |limit| limit.as_secs_f64()),
+            ..GameSettings::new(args.variant)
         };
+        let board = BitBoard::new(settings.rows, settings.cols, settings.connect).with_variant(settings.variant);
 
-        Self {
+        let mut app = Self {
+            difficulty: settings.difficulty,
+            settings,
+            in_game: false,
             game_state: board,
-            difficulty,
-            current:  current,
+            current: Piece::Player,
             game_over: false,
             message: String::new(),
             ai_move_queued: false, // Initialize ai_move_queued
+            tie_break: args.tie_break(),
+            engine: Engine::new(Searcher::new(params), args.ponder),
+            ai_search: None,
+            clock: Clock::new(args.time_control),
+            started: Instant::now(),
+            game_time: Duration::ZERO,
+            history: Vec::new(),
+            positions: Vec::new(),
+            cursor: 0,
+            hint_search: None,
+            hint: None,
+            show_help: false,
+            winning_line: Vec::new(),
+            falling: None,
+            drops: 0,
+            game_over_window: false,
+            replay: None,
+            replay_eval: None,
+            stats: Stats::load(&args.stats_file).unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                Stats::default()
+            }),
+            stats_file: args.stats_file.clone(),
+            search_progress: Vec::new(),
+            seen: HashMap::new(),
+        };
+        if let Some(number) = args.replay {
+            app.open_replay(number);
+        }
+        app
+    }
+
+    // shows game `number` of the statistics (the last one without a number)
+    fn open_replay(&mut self, number: Option<usize>) {
+        match Replay::from_stats(&self.stats, number) {
+            Ok(replay) => {
+                self.replay = Some(replay);
+                self.replay_eval = None;
+            }
+            Err(err) => eprintln!("{}", err),
+        }
+    }
+
+    // starts a new game with the current settings
+    fn start_game(&mut self) {
+        let s = &self.settings;
+        self.game_state = BitBoard::new(s.rows, s.cols, s.connect)
+            .with_variant(s.variant)
+            .with_players(s.players)
+            .with_misere(s.misere)
+            .with_blockers(&s.blocker_cells)
+            .with_random_blockers(s.blockers, s.blocker_seed)
+            .with_first(if s.player_starts { Piece::Player } else { Piece::AI });
+        self.difficulty = s.difficulty;
+        self.current = self.game_state.first;
+        self.ai_move_queued = !s.player_starts;
+        self.ai_search = None;
+        self.clock = Clock::new(s.time_control());
+        self.started = Instant::now();
+        self.history.clear();
+        self.positions.clear();
+        self.cursor = self.game_state.cols / 2;
+        self.hint_search = None;
+        self.hint = None;
+        self.winning_line.clear();
+        self.falling = None;
+        self.game_over_window = false;
+        self.game_over = false;
+        self.message.clear();
+        self.search_progress.clear();
+        self.seen.clear();
+        self.in_game = true;
+    }
+
+    fn settings_screen(&mut self, ui: &mut egui::Ui) {
+        ui.heading("New game");
+        ui.add_space(8.0);
+
+        let s = &mut self.settings;
+        egui::Grid::new("settings").num_columns(2).spacing([20.0, 8.0]).show(ui, |ui| {
+            ui.label("Variant");
+            let previous = s.variant;
+            egui::ComboBox::from_id_salt("variant")
+                .selected_text(s.variant.title())
+                .show_ui(ui, |ui| {
+                    for variant in Variant::ALL {
+                        ui.selectable_value(&mut s.variant, variant, variant.title());
+                    }
+                });
+            if s.variant != previous {
+                *s = GameSettings {
+                    misere: s.misere,
+                    blockers: s.blockers,
+                    blocker_seed: s.blocker_seed,
+                    blocker_cells: std::mem::take(&mut s.blocker_cells),
+                    difficulty: s.difficulty,
+                    player_starts: s.player_starts,
+                    clock_minutes: s.clock_minutes,
+                    increment_secs: s.increment_secs,
+                    move_limit_secs: s.move_limit_secs,
+                    ..GameSettings::new(s.variant)
+                };
+            }
+            ui.end_row();
+
+            ui.label("Rows");
+            ui.add(egui::Slider::new(&mut s.rows, 2..=20));
+            ui.end_row();
+
+            // the columns must still fit into the masks and the line onto the board
+            s.cols = s.cols.min(BitBoard::max_cols(s.rows));
+            ui.label("Columns");
+            ui.add(egui::Slider::new(&mut s.cols, 2..=BitBoard::max_cols(s.rows)));
+            ui.end_row();
+
+            s.connect = s.connect.min(s.rows.min(s.cols));
+            ui.label("Connect");
+            ui.add(egui::Slider::new(&mut s.connect, 2..=s.rows.min(s.cols)));
+            ui.end_row();
+
+            let allowed = s.variant.players();
+            s.players = s.players.clamp(*allowed.start(), *allowed.end());
+            ui.label("Players");
+            ui.add_enabled(allowed.start() != allowed.end(), egui::Slider::new(&mut s.players, allowed));
+            ui.end_row();
+
+            ui.label("Misère");
+            ui.add_enabled(s.variant.lines_win() && s.players == 2, egui::Checkbox::new(&mut s.misere, "Completing a line loses"));
+            ui.end_row();
+
+            // pops move pieces down, blocked cells only work without them
+            ui.label("Blocked cells");
+            ui.add_enabled_ui(!s.variant.has_pops(), |ui| {
+                ui.horizontal(|ui| {
+                    let most = (s.rows as u16 * s.cols as u16 / 4).min(u8::MAX as u16) as u8;
+                    s.blockers = s.blockers.min(most);
+                    ui.add(egui::Slider::new(&mut s.blockers, 0..=most));
+                    ui.label("seed");
+                    ui.add(egui::DragValue::new(&mut s.blocker_seed));
+                    if ui.button("New").clicked() {
+                        s.blocker_seed = random_seed();
+                    }
+                });
+                if !s.blocker_cells.is_empty() {
+                    ui.weak(format!("and {} fixed cells from the command line", s.blocker_cells.len()));
+                }
+            });
+            ui.end_row();
+
+            ui.label("Difficulty");
+            ui.add(egui::Slider::new(&mut s.difficulty, MIN_DIFFICULTY..=MAX_DIFFICULTY));
+            ui.end_row();
+
+            ui.label("First move");
+            ui.checkbox(&mut s.player_starts, "You start");
+            ui.end_row();
+
+            // every part of the time control is off at 0
+            ui.label("Clock");
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut s.clock_minutes).range(0.0..=180.0).speed(0.5).suffix(" min"));
+                ui.label("+");
+                ui.add_enabled(s.clock_minutes > 0.0, egui::DragValue::new(&mut s.increment_secs).range(0.0..=60.0).suffix(" s per move"));
+            });
+            ui.end_row();
+
+            ui.label("Move limit");
+            ui.add(egui::DragValue::new(&mut s.move_limit_secs).range(0.0..=600.0).suffix(" s"));
+            ui.end_row();
+        });
+
+        ui.add_space(8.0);
+        ui.label(s.variant.description());
+        ui.add_space(8.0);
+
+        let enter = keys_free(ui.ctx()) && ui.input(|i| i.key_pressed(egui::Key::Enter));
+        if ui.button("Start").on_hover_text("Enter").clicked() || enter {
+            self.start_game();
+        }
+
+        ui.add_space(8.0);
+        if let Some(number) = self.stats_panel(ui) {
+            self.open_replay(Some(number));
+        }
+    }
+
+    // plays the move and checks the end of the game - returns false if the move is not legal
+    fn apply_move(&mut self, mv: Move, piece: Piece) -> bool {
+        let new_board = match self.game_state.play(mv, piece) {
+            Some(board) => board,
+            None => return false,
+        };
+        // a move after the flag fell does not count
+        if !self.clock.stop() {
+            self.flag_fell(piece);
+            return true;
+        }
+        if let Move::Drop(col) = mv {
+            self.drops += 1;
+            let row = self.game_state.height(col);
+            self.falling = Some(DropAnimation { id: egui::Id::new(("drop", self.drops)), row, col, started: false });
+        }
+        self.positions.push((self.game_state.clone(), piece));
+        self.game_state = new_board;
+        self.current = self.game_state.next_player(piece);
+        self.hint_search = None;
+        self.hint = None;
+
+        self.history.push(mv);
+
+        if let Some(winner) = self.game_state.winner(piece) {
+            let message = match (winner, self.game_state.variant.has_teams()) {
+                (Piece::Player, _) => "You win!".to_string(),
+                (_, true) if self.game_state.same_team(winner, Piece::Player) => format!("{} connected - your team wins!", winner),
+                (_, true) => format!("{} connected - the other team wins!", winner),
+                (_, false) => format!("{} wins!", winner),
+            };
+            let result = if self.game_state.same_team(winner, Piece::Player) { GameResult::Win } else { GameResult::Loss };
+            self.end_game(message, result);
+        } else if !self.game_state.has_legal_move(self.current) {
+            self.end_game("It's a draw!".to_string(), GameResult::Draw);
+        } else {
+            let count = self.seen.entry((self.game_state.position_key(), self.current)).or_insert(0);
+            *count += 1;
+            if *count >= 3 {
+                self.end_game("Same position three times - it's a draw!".to_string(), GameResult::Draw);
+            }
+        }
+        true
+    }
+
+    // the move of the history to go back to: the last one the human made - none on a clock or after the game
+    fn undo_point(&self) -> Option<usize> {
+        if self.game_over || self.clock.control.is_timed() {
+            return None;
+        }
+        self.positions.iter().rposition(|&(_, piece)| piece == Piece::Player)
+    }
+
+    // takes back the last move of the human and the AI moves after it
+    fn undo(&mut self) {
+        let Some(point) = self.undo_point() else { return };
+        self.engine.stop_pondering();
+        self.ai_search = None;
+        self.ai_move_queued = false;
+        self.hint_search = None;
+        self.hint = None;
+        self.falling = None;
+        self.clock.stop();
+
+        // the repetitions are counted again, over the positions after the moves that are left
+        self.seen.clear();
+        for (board, piece) in &self.positions[1..=point] {
+            *self.seen.entry((board.position_key(), *piece)).or_insert(0) += 1;
+        }
+        (self.game_state, self.current) = self.positions[point].clone();
+        self.positions.truncate(point);
+        self.history.truncate(point);
+        self.search_progress.clear();
+    }
+
+    // a move for the human, searched in steps like the moves of the AI
+    fn request_hint(&mut self) {
+        if self.current != Piece::Player || self.game_over || self.hint_search.is_some() {
+            return;
+        }
+        self.engine.stop_pondering();
+        self.hint = None;
+        self.hint_search = Some(SteppedSearch::new(&self.game_state, Piece::Player, MAX_DIFFICULTY).with_time_limit(Some(HINT_TIME)));
+    }
+
+    fn flag_fell(&mut self, piece: Piece) {
+        self.clock.stop();
+        self.engine.stop_pondering();
+        self.ai_search = None;
+        self.ai_move_queued = false;
+        let result = flag_result(&self.game_state, piece);
+        self.end_game(flag_message(&self.game_state, piece), result);
+    }
+
+    // ends the game and adds it to the statistics
+    fn end_game(&mut self, message: String, result: GameResult) {
+        self.game_over = true;
+        self.message = message;
+        self.winning_line = line_on(&self.game_state, self.positions.last().map(|&(_, piece)| piece));
+        self.game_over_window = true;
+        self.game_time = self.started.elapsed();
+
+        let game = GameRecord::new(&self.game_state, self.game_state.first, &self.history, self.difficulty, result, self.game_time);
+        if let Err(err) = self.stats.record(&self.stats_file, game) {
+            self.message = format!("{} (the game was not recorded: {})", self.message, err);
+        }
+    }
+
+    // win rates per difficulty, the estimated rating and the last games - returns the game to replay
+    fn stats_panel(&self, ui: &mut egui::Ui) -> Option<usize> {
+        let mut replay = None;
+        egui::CollapsingHeader::new(format!("Statistics ({} games)", self.stats.games.len())).show(ui, |ui| {
+            let levels = self.stats.by_difficulty();
+            if levels.is_empty() {
+                ui.label("No games against a single AI yet.");
+            }
+
+            egui::Grid::new("stats").striped(true).show(ui, |ui| {
+                if levels.is_empty() {
+                    return;
+                }
+                for heading in ["Difficulty", "Games", "Wins", "Draws", "Losses", "Win rate"] {
+                    ui.strong(heading);
+                }
+                ui.end_row();
+
+                for level in &levels {
+                    ui.label(level.difficulty.to_string());
+                    ui.label(level.games().to_string());
+                    ui.label(level.wins.to_string());
+                    ui.label(level.draws.to_string());
+                    ui.label(level.losses.to_string());
+                    ui.label(format!("{:.0}%", level.win_rate() * 100.0));
+                    ui.end_row();
+                }
+            });
+            if let Some(rating) = self.stats.rating() {
+                ui.label(format!("Estimated rating: {:.0}", rating))
+                    .on_hover_text(format!("Difficulty 1 counts as {:.0}, each level adds {:.0}", level_rating(1), level_rating(2) - level_rating(1)));
+            }
+
+            for (number, game) in self.stats.recent(RECENT_GAMES) {
+                ui.horizontal(|ui| {
+                    let has_moves = game.moves == 0 || !game.history.is_empty();
+                    if ui.add_enabled(has_moves, egui::Button::new("Replay")).clicked() {
+                        replay = Some(number);
+                    }
+                    ui.label(format!("{}. {}", number, game.summary()));
+                });
+            }
+        });
+        replay
+    }
+
+    // the time left of every colour, the one to move in bold, and the move limit while it runs
+    fn clock_panel(&self, ui: &mut egui::Ui) {
+        if !self.clock.control.is_timed() {
+            return;
+        }
+        ui.horizontal(|ui| {
+            for &piece in &Piece::COLOURS[..self.game_state.players as usize] {
+                let Some(time) = self.clock.remaining(piece) else { continue };
+                let text = egui::RichText::new(format!("{} {}", piece, format_time(time))).monospace();
+                match self.clock.running() == Some(piece) {
+                    true => ui.label(text.strong()),
+                    false => ui.label(text.weak()),
+                };
+            }
+            if let (Some(_), Some(piece)) = (self.clock.control.per_move, self.clock.running()) {
+                let left = self.clock.time_for_move(piece).unwrap_or_default();
+                ui.label(egui::RichText::new(format!("move {}", format_time(left))).monospace());
+            }
+        });
+    }
+
+    // a recorded game - the arrow keys step through the moves, Home and End jump, E switches the engine
+    // evaluation and Escape closes the replay
+    fn replay_screen(&mut self, ui: &mut egui::Ui) {
+        let Some(replay) = self.replay.as_mut() else { return };
+        let key = |key| ui.input(|i| i.key_pressed(key));
+        let (mut close, mut toggle_eval) = (key(egui::Key::Escape), key(egui::Key::E));
+        if key(egui::Key::ArrowLeft) {
+            replay.back();
+        }
+        if key(egui::Key::ArrowRight) {
+            replay.forward();
+        }
+        if key(egui::Key::Home) {
+            replay.go_to(0);
+        }
+        if key(egui::Key::End) {
+            replay.go_to(replay.len());
+        }
+
+        ui.horizontal(|ui| {
+            ui.strong(format!("Replay: {}", replay.record.summary()));
+            close |= ui.button("Close").clicked();
+        });
+        ui.horizontal(|ui| {
+            if ui.button("|<").on_hover_text("Start (Home)").clicked() {
+                replay.go_to(0);
+            }
+            if ui.button("<").on_hover_text("Previous move (Left)").clicked() {
+                replay.back();
+            }
+            if ui.button(">").on_hover_text("Next move (Right)").clicked() {
+                replay.forward();
+            }
+            if ui.button(">|").on_hover_text("End (End)").clicked() {
+                replay.go_to(replay.len());
+            }
+            ui.label(replay.describe());
+        });
+
+        let board = replay.board();
+        let line = line_on(board, replay.last_move().map(|(_, piece, _)| piece));
+        ui.horizontal(|ui| {
+            for col in 0..board.cols {
+                let (response, painter) = ui.allocate_painter(egui::Vec2::new(CELL_SIZE, CELL_SIZE * board.rows as f32), egui::Sense::hover());
+                paint_column(&painter, response.rect, board, col, &line, None);
+            }
+        });
+
+        let mut evaluate = self.replay_eval.is_some();
+        toggle_eval |= ui.checkbox(&mut evaluate, "Engine evaluation (E)").changed();
+        if toggle_eval {
+            self.replay_eval = match self.replay_eval {
+                Some(_) => None,
+                // a position number no replay has, so the first frame evaluates
+                None => Some(ReplayEval { position: usize::MAX, search: None, info: None }),
+            };
+        }
+        if let Some(eval) = &mut self.replay_eval {
+            if eval.position != replay.position {
+                let search = replay.evaluation(replay.record.difficulty.max(1));
+                *eval = ReplayEval { position: replay.position, search, info: None };
+            }
+            // a step per frame, as for the AI moves
+            if let Some(search) = &mut eval.search {
+                match search.step(self.engine.searcher(), None, None) {
+                    Some(info) => (eval.search, eval.info) = (None, Some(info)),
+                    None => ui.ctx().request_repaint(),
+                }
+            }
+            match (&eval.search, &eval.info) {
+                (Some(_), _) => ui.label(format!("{} to move: thinking...", replay.to_move())),
+                (None, Some(info)) => ui.label(format!(
+                    "{} to move: {} (best {}, depth {})",
+                    replay.to_move(), info.score_text(), info.best_move.map_or("-".to_string(), |mv| mv.to_string()), info.depth
+                )),
+                (None, None) => ui.label("The game is over"),
+            };
+        }
+
+        if close {
+            self.replay = None;
+            self.replay_eval = None;
+        }
+    }
+
+    // the result over the board: rematch with the same settings, or step through the game just played
+    fn game_over_window(&mut self, ctx: &egui::Context) {
+        if !self.game_over || !self.game_over_window || self.replay.is_some() || !self.in_game || self.falling.is_some() {
+            return;
+        }
+        let (mut rematch, mut review, mut close) = (false, false, false);
+
+        egui::Window::new("Game over")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.heading(&self.message);
+                ui.label(format!("{} moves in {}", self.history.len(), format_time(self.game_time)));
+                ui.add_space(8.0);
+                ui.horizontal(|ui| {
+                    rematch = ui.button("Rematch").clicked();
+                    review = ui.button("Review").clicked();
+                    close = ui.button("Close").clicked();
+                });
+            });
+
+        if rematch {
+            self.start_game();
+        } else if review {
+            // the game was just recorded, it is the last one
+            self.game_over_window = false;
+            self.open_replay(None);
+        } else if close {
+            self.game_over_window = false;
+        }
+    }
+
+    // the human can move once the last piece has landed
+    fn player_turn(&self) -> bool {
+        self.current == Piece::Player && !self.game_over && self.falling.is_none()
+    }
+
+    // the keys of a game - returns the move of the human they make
+    fn game_keys(&mut self, ctx: &egui::Context) -> Option<Move> {
+        let key = |key| ctx.input(|i| i.key_pressed(key));
+        let cols = self.game_state.cols;
+        if key(egui::Key::ArrowLeft) {
+            self.cursor = self.cursor.saturating_sub(1);
+        }
+        if key(egui::Key::ArrowRight) {
+            self.cursor = (self.cursor + 1).min(cols - 1);
+        }
+        if key(egui::Key::U) || ctx.input(|i| i.modifiers.command && i.key_pressed(egui::Key::Z)) {
+            self.undo();
+        }
+        if key(egui::Key::H) {
+            self.request_hint();
+        }
+        if key(egui::Key::N) {
+            self.engine.stop_pondering();
+            self.in_game = false;
+        }
+        if !self.player_turn() {
+            return None;
+        }
+
+        if let Some(col) = DIGIT_KEYS.iter().position(|&digit| key(digit)).map(|col| col as u8).filter(|&col| col < cols) {
+            self.cursor = col;
+            return Some(Move::Drop(col));
+        }
+        if key(egui::Key::Enter) || key(egui::Key::Space) {
+            return Some(Move::Drop(self.cursor));
+        }
+        if key(egui::Key::P) && self.game_state.variant.has_pops() {
+            return Some(Move::Pop(self.cursor));
+        }
+        None
+    }
+
+    fn help_window(&mut self, ctx: &egui::Context) {
+        egui::Window::new("Keyboard controls")
+            .open(&mut self.show_help)
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                egui::Grid::new("keys").num_columns(2).spacing([20.0, 4.0]).show(ui, |ui| {
+                    for (keys, action) in KEYS {
+                        ui.strong(keys);
+                        ui.label(action);
+                        ui.end_row();
+                    }
+                });
+                ui.add_space(8.0);
+                ui.weak("Columns are counted from 0 on the left. Replays: Left / Right, Home / End, E and Escape.");
+            });
+    }
+
+    // statistics of the last AI search, with one row per finished depth
+    fn search_info_panel(&self, ui: &mut egui::Ui) {
+        let last = match self.search_progress.last() {
+            Some(info) => info,
+            None => return,
+        };
+
+        egui::CollapsingHeader::new("Search info").show(ui, |ui| {
+            ui.label(format!("Best move: {}", last.best_move.map_or("-".to_string(), |c| c.to_string())));
+            ui.label(format!("Score: {}", last.score_text()));
+            ui.label(format!("Nodes: {} ({}/s)", last.nodes, last.nodes_per_second()));
+            ui.label(format!("Cutoffs: {}, TT hits: {}", last.cutoffs, last.tt_hits));
+            ui.label(format!("Time: {:.2}s", last.elapsed.as_secs_f64()));
+            if self.engine.pondering_enabled {
+                ui.label(format!("Pondered to depth {} during your turn", self.engine.last_ponder_depth));
+            }
+
+            egui::Grid::new("search_progress").striped(true).show(ui, |ui| {
+                ui.strong("Depth");
+                ui.strong("Score");
+                ui.strong("Nodes");
+                ui.strong("Time");
+                ui.strong("PV");
+                ui.end_row();
+
+                for info in &self.search_progress {
+                    let pv: Vec<String> = info.pv.iter().map(|c| c.to_string()).collect();
+                    ui.label(info.depth.to_string());
+                    ui.label(info.score_text());
+                    ui.label(info.nodes.to_string());
+                    ui.label(format!("{:.2}s", info.elapsed.as_secs_f64()));
+                    ui.label(pv.join(" "));
+                    ui.end_row();
+                }
+            });
+        });
+    }
+}
+
+// the cells of the line that ended the game after the move of `last_mover` - empty while there is none
+fn line_on(board: &BitBoard, last_mover: Option<Piece>) -> Vec<(u8, u8)> {
+    last_mover.map_or_else(Vec::new, |piece| board.decisive_line(piece))
+}
+
+fn piece_color(piece: Piece) -> egui::Color32 {
+    match piece {
+        Piece::Empty => egui::Color32::GRAY,
+        Piece::Player => egui::Color32::RED,
+        Piece::AI => egui::Color32::YELLOW,
+        Piece::Third => egui::Color32::GREEN,
+        Piece::Fourth => egui::Color32::LIGHT_BLUE,
+    }
+}
+
+// middle of a cell of the column in `rect`, row 0 at the bottom
+fn cell_center(rect: egui::Rect, board: &BitBoard, row: u8) -> egui::Pos2 {
+    let y = rect.top() + CELL_SIZE * (board.rows - 1 - row) as f32 + CELL_SIZE / 2.0;
+    egui::Pos2::new(rect.left() + CELL_SIZE / 2.0, y)
+}
+
+// the cells of one column, row 0 at the bottom of `rect` - with a `line` its pieces are ringed and the
+// other pieces faded, with `fall` (row and progress from 0 to 1) the piece of that row is on its way
+// down from above the column
+fn paint_column(painter: &egui::Painter, rect: egui::Rect, board: &BitBoard, col: u8, line: &[(u8, u8)], fall: Option<(u8, f32)>) {
+    let radius = CELL_SIZE / 2.0 - CELL_SPACING;
+    let mut falling = None;
+
+    for row in 0..board.rows {
+        let piece = board.get_piece(row, col);
+        let in_line = line.contains(&(row, col));
+        let center = cell_center(rect, board, row);
+
+        // blocked cells are drawn as dark squares, not as holes for a piece
+        if board.is_blocked(row, col) {
+            let rect = egui::Rect::from_center_size(center, egui::Vec2::splat(CELL_SIZE - 2.0 * CELL_SPACING));
+            painter.rect_filled(rect, 4.0, egui::Color32::from_gray(40));
+            continue;
+        }
+        let faded = !line.is_empty() && !in_line && piece != Piece::Empty;
+        let color = if faded { piece_color(piece).gamma_multiply(0.35) } else { piece_color(piece) };
+
+        // the falling piece goes over the holes above its cell, so it is drawn last
+        if let Some((_, progress)) = fall.filter(|&(fall_row, _)| fall_row == row) {
+            painter.circle_filled(center, radius, piece_color(Piece::Empty));
+            let top = rect.top() - CELL_SIZE / 2.0;
+            falling = Some((egui::Pos2::new(center.x, top + (center.y - top) * progress), color, in_line));
+            continue;
+        }
+        painter.circle_filled(center, radius, color);
+        if in_line {
+            painter.circle_stroke(center, radius, egui::Stroke::new(3.0, egui::Color32::WHITE));
+        }
+    }
+
+    if let Some((center, color, in_line)) = falling {
+        painter.circle_filled(center, radius, color);
+        if in_line {
+            painter.circle_stroke(center, radius, egui::Stroke::new(3.0, egui::Color32::WHITE));
         }
     }
 }
 
+// a full column takes no more pieces: dimmed and crossed by diagonal lines
+fn paint_full_column(painter: &egui::Painter, rect: egui::Rect) {
+    painter.rect_filled(rect, 0.0, egui::Color32::from_black_alpha(110));
+    let stroke = egui::Stroke::new(1.5, egui::Color32::from_white_alpha(40));
+    let step = CELL_SIZE / 3.0;
+    let mut x = rect.left() - rect.height();
+    while x < rect.right() {
+        painter.line_segment([egui::Pos2::new(x, rect.bottom()), egui::Pos2::new(x + rect.height(), rect.top())], stroke);
+        x += step;
+    }
+}
+
 impl eframe::App for Connect4App {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        if keys_free(ctx) {
+            let key = |key| ctx.input(|i| i.key_pressed(key));
+            if key(egui::Key::Q) {
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            }
+            if key(egui::Key::Questionmark) || key(egui::Key::F1) {
+                self.show_help = !self.show_help;
+            }
+            if key(egui::Key::Escape) {
+                match self.show_help {
+                    true => self.show_help = false,
+                    false => self.game_over_window = false,
+                }
+            }
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
             ctx.set_visuals(Visuals::dark());
+
+            if self.replay.is_some() {
+                self.replay_screen(ui);
+                return;
+            }
+            if !self.in_game {
+                self.settings_screen(ui);
+                return;
+            }
+
+            ui.horizontal(|ui| {
+                ui.strong(self.game_state.variant.title());
+                if self.game_state.misere {
+                    ui.label("(misère - a line loses)");
+                }
+                if ui.button("New game").on_hover_text("N").clicked() {
+                    self.engine.stop_pondering();
+                    self.in_game = false;
+                }
+                if ui.add_enabled(self.undo_point().is_some(), egui::Button::new("Undo")).on_hover_text("Take back your last move (U)").clicked() {
+                    self.undo();
+                }
+                if ui.add_enabled(self.player_turn(), egui::Button::new("Hint")).on_hover_text("H").clicked() {
+                    self.request_hint();
+                }
+                if ui.button("Keys").on_hover_text("Keyboard controls (? or F1)").clicked() {
+                    self.show_help = !self.show_help;
+                }
+            });
+
+            // how far the last dropped piece has fallen - a new id is seen as being at the top, moving
+            // the target to true on the next frame starts the fall
+            let time = self.falling.as_mut().map(|drop| {
+                let time = ctx.animate_bool_with_time(drop.id, drop.started, DROP_TIME);
+                drop.started = true;
+                time
+            });
+            // the eased position, the piece lands and bounces back a little
+            let fall = self.falling.as_ref().zip(time).map(|(drop, time)| (drop.row, drop.col, egui::emath::easing::bounce_out(time)));
+            match time {
+                Some(time) if time >= 1.0 => self.falling = None,
+                Some(_) => ctx.request_repaint(),
+                None => {}
+            }
+
+            // the clock of the colour to move runs while the game does
+            if !self.game_over && self.falling.is_none() {
+                self.clock.start(self.current);
+                if let Some(piece) = self.clock.flagged() {
+                    self.flag_fell(piece);
+                } else if self.clock.control.is_timed() {
+                    ctx.request_repaint_after(Duration::from_millis(100));
+                }
+            }
+            self.clock_panel(ui);
+
+            let mut clicked_move = if keys_free(ctx) { self.game_keys(ctx) } else { None };
+            let player_turn = self.player_turn();
+
             ui.horizontal(|ui| {
                 for col in 0..self.game_state.cols {
                     ui.vertical(|ui| {
@@ -52,72 +907,166 @@ impl eframe::App for Connect4App {
                             egui::Sense::click(),
                         );
 
-                        if response.clicked() && self.current == Piece::Player && !self.game_over {
-                            if let Some(new_board) = self.game_state.drop_piece(col, Piece::Player) {
-                                self.game_state = new_board;
-                                if self.game_state.check_win(Piece::Player) {
-                                    self.game_over = true;
-                                    self.message = "You win!".to_string();
-                                } else if self.game_state.is_full() {
-                                    self.game_over = true;
-                                    self.message = "It's a draw!".to_string();
-                                } else {
-                                    self.current = Piece::AI;
-                                    self.ai_move_queued = true;
-                                    ctx.request_repaint(); // Request repaint to defer AI move to next frame
-                                }
+                        let can_drop = self.game_state.legal_moves(Piece::Player).contains(&Move::Drop(col));
+                        let response = if self.game_state.is_valid_location(col) {
+                            response
+                        } else {
+                            response.on_hover_cursor(egui::CursorIcon::NotAllowed)
+                        };
+                        if response.clicked() && player_turn && can_drop {
+                            clicked_move = Some(Move::Drop(col));
+                        }
+                        // the mouse moves the keyboard cursor, a resting pointer leaves the arrow keys alone
+                        if response.hovered() && ui.input(|i| i.pointer.delta() != egui::Vec2::ZERO) {
+                            self.cursor = col;
+                        }
+
+                        // pops: button under the column to remove the own bottom piece
+                        if self.game_state.variant.has_pops() {
+                            let can_pop = player_turn && self.game_state.can_pop(col, Piece::Player);
+                            let pop = ui.add_enabled_ui(can_pop, |ui| {
+                                ui.add_sized([CELL_SIZE, 20.0], egui::Button::new("Pop"))
+                            }).inner;
+                            if pop.clicked() {
+                                clicked_move = Some(Move::Pop(col));
                             }
                         }
 
-                        for row in 0..self.game_state.rows {
-                            let piece = self.game_state.get_piece(row, col);
-                            let color = match piece {
-                                Piece::Empty => egui::Color32::GRAY,
-                                Piece::Player => egui::Color32::RED,
-                                Piece::AI => egui::Color32::YELLOW,
+                        let column_fall = fall.filter(|&(_, fall_col, _)| fall_col == col).map(|(row, _, progress)| (row, progress));
+                        paint_column(&painter, response.rect, &self.game_state, col, &self.winning_line, column_fall);
+                        if !self.game_state.is_valid_location(col) {
+                            paint_full_column(&painter, response.rect);
+                        } else if player_turn && can_drop && col == self.cursor {
+                            // ghost of the piece in the cell a click or Enter would fill
+                            let center = cell_center(response.rect, &self.game_state, self.game_state.height(col));
+                            painter.circle_filled(center, CELL_SIZE / 2.0 - CELL_SPACING, piece_color(Piece::Player).gamma_multiply(0.4));
+                        }
+                        if player_turn && col == self.cursor {
+                            painter.rect_stroke(response.rect, 4.0, egui::Stroke::new(2.0, egui::Color32::from_white_alpha(60)), egui::StrokeKind::Inside);
+                        }
+                        if let Some(hint) = self.hint.filter(|hint| hint.col() == col) {
+                            let row = match hint {
+                                Move::Drop(_) => self.game_state.height(col),
+                                Move::Pop(_) => 0,
                             };
-
-                            // Calculate y position to invert rows (0 starts at the bottom)
-                            let y = response.rect.top() 
-                                + CELL_SIZE * (self.game_state.rows - 1 - row) as f32 
-                                + CELL_SIZE / 2.0;
-
-                            let center = egui::Pos2::new(
-                                response.rect.left() + CELL_SIZE / 2.0,
-                                y
-                            );
-
-                            painter.circle_filled(center, CELL_SIZE / 2.0 - CELL_SPACING, color);
+                            let center = cell_center(response.rect, &self.game_state, row);
+                            painter.circle_stroke(center, CELL_SIZE / 2.0 - CELL_SPACING, egui::Stroke::new(3.0, egui::Color32::LIGHT_GREEN));
                         }
                     });
                 }
             });
 
+            if let Some(mv) = clicked_move {
+                if self.apply_move(mv, Piece::Player) {
+                    self.engine.stop_pondering();
+                    if !self.game_over {
+                        self.ai_move_queued = true;
+                        ctx.request_repaint(); // Request repaint to defer AI move to next frame
+                    }
+                }
+            }
+
             // Process AI move in the next frame after player's move is rendered
-            if self.ai_move_queued && self.current == Piece::AI && !self.game_over {
-                if let Some(col) = minimax(&self.game_state, self.difficulty, i32::MIN, i32::MAX, true).0 {
-                    if let Some(new_board) = self.game_state.drop_piece(col, Piece::AI) {
-                        self.game_state = new_board;
-                        if self.game_state.check_win(Piece::AI) {
-                            self.game_over = true;
-                            self.message = "AI wins!".to_string();
-                        } else if self.game_state.is_full() {
-                            self.game_over = true;
-                            self.message = "It's a draw!".to_string();
+            if self.ai_move_queued && self.current != Piece::Player && !self.game_over && self.falling.is_none() {
+                let think_time = self.clock.think_time(&self.game_state, self.current);
+                if self.ai_search.is_none() {
+                    self.search_progress.clear();
+                }
+                let search = self.ai_search.get_or_insert_with(|| {
+                    SteppedSearch::new(&self.game_state, self.current, self.difficulty).with_time_limit(think_time)
+                });
+                // the depths come in over several frames, each one is kept as it is finished
+                let progress = &mut self.search_progress;
+                let step = search.step(
+                    self.engine.searcher(),
+                    self.tie_break.as_mut(),
+                    Some(&mut |info: &SearchInfo| progress.push(info.clone())),
+                );
+                match step {
+                    // not done yet - the frame is drawn and the search goes on in the next one
+                    None => ctx.request_repaint(),
+                    Some(info) => {
+                        self.ai_search = None;
+
+                        if let Some(mv) = info.best_move {
+                            self.apply_move(mv, self.current);
                         } else {
-                            self.current = Piece::Player;
+                            self.game_over = true;
+                            self.message = format!("{} has no valid moves!", self.current);
+                        }
+
+                        // with more players the next AI moves in the following frame
+                        self.ai_move_queued = self.current != Piece::Player;
+                        if self.ai_move_queued {
+                            ctx.request_repaint();
                         }
                     }
-                } else {
-                    self.game_over = true;
-                    self.message = "AI has no valid moves!".to_string();
                 }
-                self.ai_move_queued = false; // Reset the flag
+            }
+
+            if let Some(search) = self.hint_search.as_mut() {
+                match search.step(self.engine.searcher(), None, None) {
+                    None => ctx.request_repaint(),
+                    Some(info) => {
+                        self.hint_search = None;
+                        self.hint = info.best_move;
+                    }
+                }
+            }
+
+            // think on the human's time (does nothing when pondering is off or already running)
+            if self.current == Piece::Player && !self.game_over && self.hint_search.is_none() {
+                self.engine.start_pondering(&self.game_state, self.difficulty);
+            }
+
+            if let Some(target) = self.game_state.variant.pieces_to_win() {
+                ui.label(format!(
+                    "Kept pieces - you: {}, AI: {} (first to {}){}",
+                    self.game_state.kept(Piece::Player),
+                    self.game_state.kept(Piece::AI),
+                    target,
+                    if self.game_state.pop_phase() { "" } else { " - fill the board first" },
+                ));
             }
 
             if self.game_over {
                 ui.label(&self.message);
             }
+            if self.hint_search.is_some() {
+                ui.weak("Looking for a hint...");
+            }
+            if let Some(hint) = self.hint {
+                ui.label(match hint {
+                    Move::Drop(col) => format!("Hint: drop into column {}", col),
+                    Move::Pop(col) => format!("Hint: pop column {}", col),
+                });
+            } else if player_turn && self.hint_search.is_none() {
+                ui.weak("Click a column or press its number - ? shows the keys");
+            }
+
+            if let Some(tie_break) = &self.tie_break {
+                ui.weak(format!("Seed: {}", tie_break.seed));
+            }
+            if self.engine.is_pondering() {
+                ui.weak("AI is thinking on your time...");
+            } else if self.ai_search.is_some() {
+                ui.weak("AI is thinking...");
+            }
+
+            self.search_info_panel(ui);
+            if self.game_over {
+                if let Some(number) = self.stats_panel(ui) {
+                    self.open_replay(Some(number));
+                }
+            }
         });
+
+        self.game_over_window(ctx);
+        self.help_window(ctx);
     }
-}
\ No newline at end of file
+}
+
+// keys go to the game unless a text field or a focused widget takes them
+fn keys_free(ctx: &egui::Context) -> bool {
+    !ctx.wants_keyboard_input() && ctx.memory(|memory| memory.focused().is_none())
+}
@@ -0,0 +1,127 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+// named rule presets - the board keeps its variant and asks it which moves are legal and how the game ends
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug, Default)]
+pub enum Variant {
+    #[default]
+    Classic,        // drops only, a line wins
+    PopOut,         // drops or pops of own bottom pieces, a line wins
+    Pop10,          // simplified Pop 10: fill the board, then only pops - a piece popped out of an own line is
+                    // kept, 10 kept pieces win (no choice where other pieces go back, no extra turn)
+    FiveInARow,     // classic rules, 5 in a row on a wider board
+    Connect4x4,     // four colours in two teams, a line of one colour wins for its team
+    Cylinder,       // classic rules, lines wrap from the last column to the first
+}
+
+impl Variant {
+    pub const ALL: [Variant; 6] = [
+        Variant::Classic,
+        Variant::PopOut,
+        Variant::Pop10,
+        Variant::FiveInARow,
+        Variant::Connect4x4,
+        Variant::Cylinder,
+    ];
+
+    // name used on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Variant::Classic => "classic",
+            Variant::PopOut => "pop-out",
+            Variant::Pop10 => "pop-10",
+            Variant::FiveInARow => "five-in-a-row",
+            Variant::Connect4x4 => "connect-4x4",
+            Variant::Cylinder => "cylinder",
+        }
+    }
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Variant::Classic => "Classic",
+            Variant::PopOut => "Pop Out",
+            Variant::Pop10 => "Pop 10",
+            Variant::FiveInARow => "Five-in-a-Row",
+            Variant::Connect4x4 => "Connect 4x4",
+            Variant::Cylinder => "Cylinder",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Variant::Classic => "Drop pieces, the first to connect a line wins.",
+            Variant::PopOut => "Drop a piece or pop one of your own from the bottom row. A line wins - if a pop completes lines for both, the popping player wins.",
+            Variant::Pop10 => "Fill the board first, lines do not count yet. Then pop your own pieces from the bottom row: a piece that was part of your line is kept, any other goes back on top of its column. The first to keep 10 pieces wins. A simplified Pop 10: in the published rules you choose the column a piece goes back into, and keeping a piece gives another turn.",
+            Variant::FiveInARow => "Classic rules with five in a row on a 6x9 board.",
+            Variant::Connect4x4 => "Four players in two teams on a wider board: you and the green AI against the yellow and blue AIs. A line of one colour wins for its team.",
+            Variant::Cylinder => "Classic rules on a cylinder: horizontal and diagonal lines continue from the last column to the first.",
+        }
+    }
+
+    // rows, columns and line length the variant is played with
+    pub fn default_size(self) -> (u8, u8, u8) {
+        match self {
+            Variant::FiveInARow => (6, 9, 5),
+            Variant::Connect4x4 => (6, 10, 4),
+            _ => (6, 7, 4),
+        }
+    }
+
+    // how many colours can play (pops and kept pieces are only defined for two)
+    pub fn players(self) -> RangeInclusive<u8> {
+        match self {
+            Variant::Classic | Variant::FiveInARow | Variant::Cylinder => 2..=4,
+            Variant::PopOut | Variant::Pop10 => 2..=2,
+            Variant::Connect4x4 => 4..=4,
+        }
+    }
+
+    // colours play in two teams, first and third against second and fourth
+    pub fn has_teams(self) -> bool {
+        self == Variant::Connect4x4
+    }
+
+    // the first and last column are neighbours
+    pub fn wraps(self) -> bool {
+        self == Variant::Cylinder
+    }
+
+    // pieces can be removed from the bottom of a column
+    pub fn has_pops(self) -> bool {
+        matches!(self, Variant::PopOut | Variant::Pop10)
+    }
+
+    // a completed line ends the game (otherwise the game is scored with kept pieces)
+    pub fn lines_win(self) -> bool {
+        self != Variant::Pop10
+    }
+
+    // number of kept pieces that wins the game
+    pub fn pieces_to_win(self) -> Option<u8> {
+        match self {
+            Variant::Pop10 => Some(10),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.title())
+    }
+}
+
+impl FromStr for Variant {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_lowercase();
+        Variant::ALL.into_iter()
+            .find(|v| v.name() == name)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Variant::ALL.iter().map(|v| v.name()).collect();
+                format!("Unknown variant: {} (expected one of {})", s.trim(), names.join(", "))
+            })
+    }
+}
@@ -0,0 +1,290 @@
+mod common;
+
+use common::{board_sizes, max_cols, naive_check_win, other, place};
+use connect_4::bitboard::{BitBoard, Move, Piece};
+use connect_4::variant::Variant;
+use proptest::prelude::*;
+
+#[test]
+fn drop_piece_stacks_from_the_bottom() {
+    let board = BitBoard::new(6, 7, 4);
+    let board = board.drop_piece(3, Piece::Player).unwrap();
+    let board = board.drop_piece(3, Piece::AI).unwrap();
+
+    assert_eq!(board.get_piece(0, 3), Piece::Player);
+    assert_eq!(board.get_piece(1, 3), Piece::AI);
+    assert_eq!(board.get_piece(2, 3), Piece::Empty);
+    assert_eq!(board.height(3), 2);
+}
+
+#[test]
+fn drop_piece_leaves_the_original_unchanged() {
+    let board = BitBoard::new(6, 7, 4);
+    let _ = board.drop_piece(0, Piece::AI).unwrap();
+
+    assert_eq!(board.get_piece(0, 0), Piece::Empty);
+    assert_eq!(board.height(0), 0);
+}
+
+#[test]
+fn drop_piece_into_full_column_fails() {
+    for (rows, cols) in board_sizes() {
+        let mut board = BitBoard::new(rows, cols, 2);
+        for _ in 0..rows {
+            board = board.drop_piece(cols - 1, Piece::Player).unwrap();
+        }
+
+        assert!(board.drop_piece(cols - 1, Piece::AI).is_none());
+        assert!(!board.is_valid_location(cols - 1));
+        assert!(!board.get_valid_locations().contains(&(cols - 1)));
+    }
+}
+
+#[test]
+fn full_board_has_no_valid_locations() {
+    for (rows, cols) in board_sizes() {
+        let mut board = BitBoard::new(rows, cols, 2);
+        let mut piece = Piece::Player;
+
+        for col in 0..cols {
+            assert!(!board.is_full());
+            for _ in 0..rows {
+                board = board.drop_piece(col, piece).unwrap();
+                piece = other(piece);
+            }
+        }
+
+        assert!(board.is_full());
+        assert!(board.get_valid_locations().is_empty());
+    }
+}
+
+// a line of exactly `connect` pieces in every direction and every position, on every legal board size
+#[test]
+fn check_win_in_every_direction_on_every_size() {
+    for (rows, cols) in board_sizes() {
+        for connect in 2..=rows.min(cols) {
+            for (dr, dc) in [(1i32, 0i32), (0, 1), (1, 1), (-1, 1)] {
+                for r in 0..rows as i32 {
+                    for c in 0..cols as i32 {
+                        let end = (r + (connect as i32 - 1) * dr, c + (connect as i32 - 1) * dc);
+                        if end.0 < 0 || end.0 >= rows as i32 || end.1 >= cols as i32 {
+                            continue;
+                        }
+
+                        let mut board = BitBoard::new(rows, cols, connect);
+                        for i in 0..connect as i32 {
+                            place(&mut board, (r + i * dr) as u8, (c + i * dc) as u8, Piece::AI);
+                        }
+                        assert!(board.check_win(Piece::AI), "{}x{} connect {} at ({}, {}) step ({}, {})", rows, cols, connect, r, c, dr, dc);
+                        assert!(!board.check_win(Piece::Player));
+
+                        // one piece short is not a win
+                        let mut short = BitBoard::new(rows, cols, connect);
+                        for i in 0..connect as i32 - 1 {
+                            place(&mut short, (r + i * dr) as u8, (c + i * dc) as u8, Piece::AI);
+                        }
+                        assert!(!short.check_win(Piece::AI));
+                    }
+                }
+            }
+        }
+    }
+}
+
+// pieces at the top of one column and the bottom of the next are neighbours in the bit order
+#[test]
+fn lines_do_not_wrap_between_columns() {
+    for (rows, cols) in board_sizes() {
+        for connect in 2..=rows.min(cols) {
+            let mut board = BitBoard::new(rows, cols, connect);
+            place(&mut board, rows - 1, 0, Piece::Player);
+            for row in 0..connect - 1 {
+                place(&mut board, row, 1, Piece::Player);
+            }
+
+            assert_eq!(board.check_win(Piece::Player), naive_check_win(&board, Piece::Player));
+        }
+    }
+}
+
+#[test]
+fn pop_removes_the_bottom_piece_and_shifts_the_column() {
+    let board = BitBoard::new(6, 7, 4).with_variant(Variant::PopOut)
+        .drop_piece(2, Piece::Player).unwrap()
+        .drop_piece(2, Piece::AI).unwrap()
+        .drop_piece(2, Piece::Player).unwrap()
+        .drop_piece(3, Piece::AI).unwrap();
+
+    let popped = board.pop_piece(2, Piece::Player).unwrap();
+    assert_eq!(popped.get_piece(0, 2), Piece::AI);
+    assert_eq!(popped.get_piece(1, 2), Piece::Player);
+    assert_eq!(popped.get_piece(2, 2), Piece::Empty);
+    assert_eq!(popped.height(2), 2);
+    // the neighbouring columns are untouched
+    assert_eq!(popped.get_piece(0, 3), Piece::AI);
+    assert_eq!(popped.height(3), 1);
+}
+
+#[test]
+fn only_own_bottom_pieces_can_be_popped() {
+    let board = BitBoard::new(6, 7, 4).drop_piece(0, Piece::Player).unwrap();
+    assert!(!board.can_pop(0, Piece::Player), "classic rules have no pops");
+
+    let board = board.with_variant(Variant::PopOut);
+    assert!(board.can_pop(0, Piece::Player));
+    assert!(!board.can_pop(0, Piece::AI));
+    assert!(!board.can_pop(1, Piece::Player), "empty column");
+    assert!(board.pop_piece(0, Piece::AI).is_none());
+
+    let moves = board.legal_moves(Piece::Player);
+    assert_eq!(moves.len(), 8);
+    assert!(moves.contains(&Move::Pop(0)));
+    assert!(!board.legal_moves(Piece::AI).contains(&Move::Pop(0)));
+}
+
+#[test]
+fn full_board_with_pop_out_still_has_moves() {
+    let mut board = BitBoard::new(2, 2, 2).with_variant(Variant::PopOut);
+    for (col, piece) in [(0, Piece::Player), (1, Piece::AI), (0, Piece::AI), (1, Piece::Player)] {
+        board = board.drop_piece(col, piece).unwrap();
+    }
+
+    assert!(board.is_full());
+    assert!(board.has_legal_move(Piece::Player));
+    assert_eq!(board.legal_moves(Piece::Player), vec![Move::Pop(0)]);
+    assert!(!board.clone().with_variant(Variant::Classic).has_legal_move(Piece::Player));
+}
+
+#[test]
+fn pop_completing_both_lines_wins_for_the_popping_player() {
+    // popping column 3 completes the AI line on the bottom row and the player line on the second row
+    let mut board = BitBoard::new(6, 7, 4).with_variant(Variant::PopOut);
+    for col in 0..3 {
+        board = board.drop_piece(col, Piece::AI).unwrap().drop_piece(col, Piece::Player).unwrap();
+    }
+    board = board.drop_piece(3, Piece::Player).unwrap()
+        .drop_piece(3, Piece::AI).unwrap()
+        .drop_piece(3, Piece::Player).unwrap();
+
+    let popped = board.play(Move::Pop(3), Piece::Player).unwrap();
+    assert!(popped.check_win(Piece::Player) && popped.check_win(Piece::AI));
+    assert_eq!(popped.winner(Piece::Player), Some(Piece::Player));
+}
+
+#[test]
+fn decisive_line_is_the_one_of_the_popping_colour() {
+    // the AI pops column 3: the player line on the bottom row and the AI line on the second row
+    let mut board = BitBoard::new(6, 7, 4).with_variant(Variant::PopOut);
+    for col in 0..3 {
+        board = board.drop_piece(col, Piece::Player).unwrap().drop_piece(col, Piece::AI).unwrap();
+    }
+    board = board.drop_piece(3, Piece::AI).unwrap()
+        .drop_piece(3, Piece::Player).unwrap()
+        .drop_piece(3, Piece::AI).unwrap();
+
+    let popped = board.play(Move::Pop(3), Piece::AI).unwrap();
+    assert!(popped.check_win(Piece::Player) && popped.check_win(Piece::AI));
+    assert_eq!(popped.winner(Piece::AI), Some(Piece::AI));
+    let second_row: Vec<_> = (0..4).map(|col| (1, col)).collect();
+    assert_eq!(popped.decisive_line(Piece::AI), second_row);
+
+    // in misère the AI loses by its line, which is still the one shown
+    let misere = board.clone().with_misere(true).play(Move::Pop(3), Piece::AI).unwrap();
+    assert_eq!(misere.winner(Piece::AI), Some(Piece::Player));
+    assert_eq!(misere.decisive_line(Piece::AI), second_row);
+
+    assert!(board.decisive_line(Piece::Player).is_empty());
+}
+
+#[test]
+fn moves_parse_and_print() {
+    assert_eq!("3".parse::<Move>(), Ok(Move::Drop(3)));
+    assert_eq!("p4".parse::<Move>(), Ok(Move::Pop(4)));
+    assert_eq!(Move::Pop(4).to_string(), "p4");
+    assert!("x".parse::<Move>().is_err());
+}
+
+#[test]
+fn winning_line_lists_the_cells_of_the_line() {
+    let mut board = BitBoard::new(6, 7, 4);
+    for (row, col) in [(0, 1), (1, 2), (2, 3), (3, 4)] {
+        place(&mut board, row, col, Piece::AI);
+    }
+    place(&mut board, 0, 0, Piece::AI);
+    assert_eq!(board.winning_line(Piece::AI), vec![(0, 1), (1, 2), (2, 3), (3, 4)]);
+    assert!(board.winning_line(Piece::Player).is_empty());
+
+    // five in a row is one line of five cells, a second line through the same piece adds its cells
+    let mut board = BitBoard::new(6, 7, 4);
+    for col in 1..6 {
+        place(&mut board, 0, col, Piece::Player);
+    }
+    assert_eq!(board.winning_line(Piece::Player), (1..6).map(|col| (0, col)).collect::<Vec<_>>());
+    for row in 1..4 {
+        place(&mut board, row, 5, Piece::Player);
+    }
+    let cells = board.winning_line(Piece::Player);
+    assert_eq!(cells.len(), 8);
+    assert!(cells.contains(&(3, 5)) && cells.contains(&(0, 1)));
+
+    // on the cylinder the line goes over the seam
+    let mut board = BitBoard::new(6, 7, 4).with_variant(Variant::Cylinder);
+    for col in [5, 6, 0, 1] {
+        place(&mut board, 0, col, Piece::AI);
+    }
+    assert_eq!(board.winning_line(Piece::AI), vec![(0, 0), (0, 1), (0, 5), (0, 6)]);
+}
+
+fn board_size() -> impl Strategy<Value = (u8, u8, u8)> {
+    (2..=20u8)
+        .prop_flat_map(|rows| (Just(rows), 2..=max_cols(rows)))
+        .prop_flat_map(|(rows, cols)| (Just(rows), Just(cols), 2..=rows.min(cols)))
+}
+
+// random games (moves are indices into the valid columns, so every move is legal)
+fn random_game() -> impl Strategy<Value = ((u8, u8, u8), Vec<usize>)> {
+    board_size().prop_flat_map(|size| {
+        let cells = size.0 as usize * size.1 as usize;
+        (Just(size), prop::collection::vec(any::<usize>(), 0..=cells))
+    })
+}
+
+proptest! {
+    #[test]
+    fn random_games_match_naive_reference(((rows, cols, connect), moves) in random_game()) {
+        let mut board = BitBoard::new(rows, cols, connect);
+        let mut grid = vec![vec![Piece::Empty; rows as usize]; cols as usize];
+        let mut piece = Piece::Player;
+
+        for choice in moves {
+            let valid = board.get_valid_locations();
+            let col = valid[choice % valid.len()];
+
+            let height = grid[col as usize].iter().filter(|&&p| p != Piece::Empty).count();
+            board = board.drop_piece(col, piece).unwrap();
+            grid[col as usize][height] = piece;
+            piece = other(piece);
+
+            for c in 0..cols {
+                let filled = grid[c as usize].iter().filter(|&&p| p != Piece::Empty).count() as u8;
+                prop_assert_eq!(board.height(c), filled);
+                prop_assert_eq!(board.is_valid_location(c), filled < rows);
+                for r in 0..rows {
+                    prop_assert_eq!(board.get_piece(r, c), grid[c as usize][r as usize]);
+                }
+            }
+
+            prop_assert_eq!(board.check_win(Piece::Player), naive_check_win(&board, Piece::Player));
+            prop_assert_eq!(board.check_win(Piece::AI), naive_check_win(&board, Piece::AI));
+            prop_assert_eq!(board.is_full(), board.get_valid_locations().is_empty());
+
+            for colour in [Piece::Player, Piece::AI] {
+                let line = board.winning_line(colour);
+                prop_assert_eq!(line.is_empty(), !board.check_win(colour));
+                prop_assert!(line.is_empty() || line.len() >= connect as usize);
+                prop_assert!(line.iter().all(|&(r, c)| board.get_piece(r, c) == colour));
+            }
+        }
+    }
+}
@@ -0,0 +1,143 @@
+mod common;
+
+use common::{args, naive_check_win, place};
+use connect_4::bitboard::{BitBoard, Piece};
+use connect_4::minimax::evaluate_heuristic;
+use connect_4::params::EvalParams;
+use connect_4::search::Searcher;
+use connect_4::variant::Variant;
+use proptest::prelude::*;
+
+#[test]
+fn pieces_stop_on_top_of_blockers() {
+    let board = BitBoard::new(4, 3, 3).with_blockers(&[(0, 0), (2, 0), (3, 1), (9, 9)]);
+    assert_eq!(board.blocked_cells(), vec![(0, 0), (2, 0), (3, 1)]);
+    // a blocked cell holds up the pieces like any other, the free cells under it are never reached
+    assert_eq!((board.height(0), board.height(1), board.height(2)), (3, 4, 0));
+    assert!(!board.is_valid_location(1));
+
+    // column 0: the piece lands on the upper blocked cell, not in the free cell between the two
+    let board = board.drop_piece(0, Piece::Player).unwrap();
+    assert_eq!((board.get_piece(3, 0), board.get_piece(1, 0)), (Piece::Player, Piece::Empty));
+    assert!(!board.is_valid_location(0));
+
+    // full with free cells left under the blocked ones
+    let board = (0..4).fold(board, |b, _| b.drop_piece(2, Piece::AI).unwrap());
+    assert!(board.is_full());
+    assert_eq!(board.occupied() & board.blocked_mask, 0);
+
+    let text = board.to_string();
+    assert_eq!(text.matches('#').count(), 3, "{}", text);
+}
+
+#[test]
+fn blockers_break_lines() {
+    let mut board = BitBoard::new(6, 7, 4).with_blockers(&[(0, 3)]);
+    for col in [0, 1, 2, 4, 5, 6] {
+        place(&mut board, 0, col, Piece::Player);
+    }
+    assert!(!board.check_win(Piece::Player));
+    assert_eq!(board.line_cells(Piece::Player), 0);
+
+    // the same row without the blocker is a line
+    let mut open = board.clone().with_blockers(&[]);
+    place(&mut open, 0, 3, Piece::Player);
+    assert!(open.check_win(Piece::Player));
+}
+
+#[test]
+fn random_blockers_follow_the_seed() {
+    let board = BitBoard::new(6, 7, 4);
+    let a = board.clone().with_random_blockers(5, 42);
+    let b = board.clone().with_random_blockers(5, 42);
+    assert_eq!(a.blocked_mask, b.blocked_mask);
+    assert_eq!(a.blocked_mask.count_ones(), 5);
+    assert_ne!(a.blocked_mask, board.clone().with_random_blockers(5, 43).blocked_mask);
+
+    // fixed cells stay, random ones are added next to them
+    let both = board.clone().with_blockers(&[(5, 6)]).with_random_blockers(3, 1);
+    assert!(both.is_blocked(5, 6));
+    assert_eq!(both.blocked_mask.count_ones(), 4);
+
+    // more blockers than cells blocks the whole board
+    assert!(BitBoard::new(2, 2, 2).with_random_blockers(10, 7).is_full());
+
+    // pops move pieces through the cells, the variants have no blockers
+    assert_eq!(board.clone().with_variant(Variant::PopOut).with_random_blockers(5, 42).blocked_mask, 0);
+    let cleared = a.with_variant(Variant::Pop10);
+    assert_eq!((cleared.blocked_mask, cleared.height(0)), (0, 0));
+}
+
+#[test]
+fn blockers_are_set_on_the_command_line() {
+    let random = args(&["--blockers", "4", "--blocker-seed", "9"]).unwrap();
+    assert_eq!((random.blocker_count, random.blocker_seed), (4, 9));
+    let board = random.place_blockers(BitBoard::new(6, 7, 4));
+    assert_eq!(board.blocked_mask, BitBoard::new(6, 7, 4).with_random_blockers(4, 9).blocked_mask);
+
+    let fixed = args(&["--blockers", "0:3, 2:5"]).unwrap();
+    assert_eq!(fixed.blocker_cells, vec![(0, 3), (2, 5)]);
+    assert_eq!(fixed.place_blockers(BitBoard::new(6, 7, 4)).blocked_cells(), vec![(0, 3), (2, 5)]);
+
+    assert!(args(&["--blockers", "0-3"]).is_err());
+    assert!(args(&["--blockers", "3", "--pop-out"]).is_err());
+    assert!(args(&["--blockers", "3", "--variant", "cylinder"]).is_ok());
+}
+
+#[test]
+fn evaluation_and_search_respect_blockers() {
+    // the AI has three in the bottom row, the cell that would complete them is blocked
+    let mut board = BitBoard::new(6, 7, 4);
+    for (col, piece) in [(1, Piece::AI), (1, Piece::Player), (2, Piece::AI), (2, Piece::Player), (3, Piece::AI), (6, Piece::Player)] {
+        board = board.drop_piece(col, piece).unwrap();
+    }
+    let blocked = board.clone().with_blockers(&[(0, 0), (0, 4)]);
+
+    let params = EvalParams::default();
+    assert!(
+        evaluate_heuristic(&blocked, Piece::AI, Piece::AI, &params)
+            < evaluate_heuristic(&board, Piece::AI, Piece::AI, &params)
+    );
+
+    // with both ends blocked there is no win in one, and no move lands on a blocked cell
+    let info = Searcher::new(params.clone()).search(&blocked, 1, None, None);
+    assert!(info.score < 100_000_000);
+    let after = blocked.play(info.best_move.unwrap(), Piece::AI).unwrap();
+    assert_eq!(after.occupied() & after.blocked_mask, 0);
+
+    let info = Searcher::new(params).search(&board, 1, None, None);
+    assert!(info.score >= 100_000_000);
+}
+
+proptest! {
+    #[test]
+    fn random_games_with_blockers_match_naive_reference(
+        count in 0..12u8,
+        seed in any::<u64>(),
+        moves in prop::collection::vec(any::<usize>(), 0..42),
+    ) {
+        let mut board = BitBoard::new(6, 7, 4).with_random_blockers(count, seed);
+        let blocked = board.blocked_mask;
+        let mut piece = Piece::Player;
+
+        for choice in moves {
+            let valid = board.get_valid_locations();
+            if valid.is_empty() {
+                break;
+            }
+            let col = valid[choice % valid.len()];
+            let row = board.height(col);
+            // pieces never land on or pass a blocked cell
+            prop_assert!((row..board.rows).all(|r| !board.is_blocked(r, col)));
+
+            board = board.drop_piece(col, piece).unwrap();
+            prop_assert_eq!(board.get_piece(row, col), piece);
+            prop_assert_eq!(board.blocked_mask, blocked);
+            prop_assert_eq!(board.occupied() & blocked, 0);
+            piece = piece.opponent();
+
+            prop_assert_eq!(board.check_win(Piece::Player), naive_check_win(&board, Piece::Player), "{}", board);
+            prop_assert_eq!(board.check_win(Piece::AI), naive_check_win(&board, Piece::AI), "{}", board);
+        }
+    }
+}
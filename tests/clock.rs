@@ -0,0 +1,120 @@
+mod common;
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::args;
+use connect_4::bitboard::{BitBoard, Piece};
+use connect_4::clock::{flag_message, flag_result, format_time, Clock, TimeControl};
+use connect_4::stats::GameResult;
+use connect_4::variant::Variant;
+use connect_4::engine::SteppedSearch;
+use connect_4::params::EvalParams;
+use connect_4::search::Searcher;
+
+fn millis(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+#[test]
+fn time_controls_are_parsed() {
+    assert_eq!("5".parse(), Ok(TimeControl { base: Some(Duration::from_secs(300)), ..TimeControl::default() }));
+    assert_eq!("0.5+2".parse(), Ok(TimeControl { base: Some(Duration::from_secs(30)), increment: Duration::from_secs(2), per_move: None }));
+    for bad in ["", "0", "-1", "5+", "5+x", "x+2", "inf"] {
+        assert!(bad.parse::<TimeControl>().is_err(), "{}", bad);
+    }
+
+    let both = args(&["--move-limit", "10", "--clock", "3+2"]).unwrap().time_control;
+    assert_eq!(both.to_string(), "3 min, +2 s per move, at most 10 s a move");
+    assert!(!args(&[]).unwrap().time_control.is_timed());
+    assert!(args(&["--move-limit", "0"]).is_err());
+    assert!(args(&["--clock", "5", "--host", "4000"]).is_err());
+}
+
+#[test]
+fn increment_is_added_after_a_move_in_time() {
+    let mut clock = Clock::new("1+2".parse().unwrap());
+    clock.start(Piece::Player);
+    thread::sleep(millis(20));
+    clock.start(Piece::Player); // still the same move
+    assert!(clock.stop());
+
+    let left = clock.remaining(Piece::Player).unwrap();
+    assert!(left > Duration::from_secs(61) && left <= Duration::from_secs(62) - millis(20), "{:?}", left);
+    assert_eq!(clock.remaining(Piece::AI), Some(Duration::from_secs(60)));
+    assert_eq!(clock.running(), None);
+}
+
+#[test]
+fn flag_falls_on_the_clock_and_on_the_move_limit() {
+    let mut clock = Clock::new(TimeControl { base: Some(millis(30)), ..TimeControl::default() });
+    clock.start(Piece::AI);
+    assert_eq!(clock.flagged(), None);
+    thread::sleep(millis(40));
+    assert_eq!(clock.flagged(), Some(Piece::AI));
+    assert!(!clock.stop());
+    assert_eq!(clock.remaining(Piece::AI), Some(Duration::ZERO));
+
+    // a move limit alone has no clock to show, only the time of the move
+    let mut clock = Clock::new(TimeControl { per_move: Some(millis(30)), ..TimeControl::default() });
+    assert_eq!(clock.remaining(Piece::Player), None);
+    clock.start(Piece::Player);
+    assert!(clock.stop());
+    clock.start(Piece::AI);
+    thread::sleep(millis(40));
+    assert_eq!(clock.time_for_move(Piece::AI), Some(Duration::ZERO));
+    assert!(!clock.stop());
+
+    let board = BitBoard::new(6, 7, 4);
+    assert_eq!(flag_message(&board, Piece::AI), "AI ran out of time - Player wins!");
+    assert_eq!((format_time(Duration::from_secs(245)), format_time(millis(9300))), ("4:05".to_string(), "9.3".to_string()));
+}
+
+#[test]
+fn flag_of_a_teammate_loses_for_the_team() {
+    // connect-4x4: X and + against O and *
+    let board = BitBoard::new(8, 8, 4).with_variant(Variant::Connect4x4).with_players(4);
+    assert_eq!(flag_result(&board, Piece::Third), GameResult::Loss);
+    assert_eq!(flag_message(&board, Piece::Third), "AI 2 ran out of time - the other team wins!");
+    assert_eq!(flag_result(&board, Piece::Fourth), GameResult::Win);
+    assert_eq!(flag_message(&board, Piece::Fourth), "AI 3 ran out of time - your team wins!");
+
+    // without teams only the human's own flag loses
+    let board = BitBoard::new(6, 7, 4).with_players(3);
+    assert_eq!(flag_result(&board, Piece::Player), GameResult::Loss);
+    assert_eq!(flag_result(&board, Piece::Third), GameResult::Win);
+}
+
+#[test]
+fn time_manager_shares_the_clock_over_the_game() {
+    let board = BitBoard::new(6, 7, 4);
+    assert_eq!(Clock::new(TimeControl::default()).think_time(&board, Piece::AI), None);
+
+    // 42 free cells are 21 moves each
+    let clock = Clock::new("2.1+4".parse().unwrap());
+    assert_eq!(clock.think_time(&board, Piece::AI), Some(Duration::from_secs(6) + Duration::from_secs(3)));
+
+    // near the end it keeps a few moves in reserve, and always stays under the move limit
+    let full = (0..40).fold(board.clone(), |b, i| b.drop_piece(i % 7, if i % 2 == 0 { Piece::Player } else { Piece::AI }).unwrap());
+    let clock = Clock::new(TimeControl { base: Some(Duration::from_secs(50)), increment: Duration::ZERO, per_move: Some(Duration::from_secs(1)) });
+    let time = clock.think_time(&full, Piece::AI).unwrap();
+    assert!(time < Duration::from_secs(1), "{:?}", time);
+    let clock = Clock::new("1".parse().unwrap());
+    assert_eq!(clock.think_time(&full, Piece::AI), Some(Duration::from_secs(12)));
+}
+
+#[test]
+fn stepped_search_keeps_to_its_time() {
+    let mut searcher = Searcher::new(EvalParams::default());
+    let mut search = SteppedSearch::new(&BitBoard::new(6, 7, 4), Piece::AI, 40).with_time_limit(Some(millis(100)));
+
+    let start = Instant::now();
+    let info = loop {
+        if let Some(info) = search.step(&mut searcher, None, None) {
+            break info;
+        }
+    };
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert!(info.depth >= 1 && info.depth < 40);
+    assert!(info.best_move.is_some());
+}
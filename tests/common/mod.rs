@@ -0,0 +1,151 @@
+// helpers shared by the integration tests - slow but obviously correct versions of the bitboard logic
+#![allow(dead_code)]
+
+use std::cmp::{max, min};
+
+use connect_4::bitboard::{BitBoard, Piece};
+use connect_4::cli::Args;
+use connect_4::minimax::{evaluate_heuristic, WIN_SCORE};
+use connect_4::params::EvalParams;
+
+// the command line from a list of words
+pub fn args(list: &[&str]) -> Result<Args, String> {
+    Args::parse_from(list.iter().map(|s| s.to_string()))
+}
+
+// every legal (rows, cols) size - the board and padding bits must fit in a u128
+pub fn board_sizes() -> Vec<(u8, u8)> {
+    let mut sizes = Vec::new();
+    for rows in 2..=20u8 {
+        for cols in 2..=max_cols(rows) {
+            sizes.push((rows, cols));
+        }
+    }
+    sizes
+}
+
+pub fn max_cols(rows: u8) -> u8 {
+    128 / (rows + 1) - 1
+}
+
+pub fn other(piece: Piece) -> Piece {
+    match piece {
+        Piece::Player => Piece::AI,
+        Piece::AI => Piece::Player,
+        _ => Piece::Empty,
+    }
+}
+
+// sets the piece directly in the masks, without gravity
+pub fn place(board: &mut BitBoard, row: u8, col: u8, piece: Piece) {
+    let bit = 1u128 << (col as u32 * (board.rows as u32 + 1) + row as u32);
+    match piece {
+        Piece::Player => board.player_mask |= bit,
+        Piece::AI => board.ai_mask |= bit,
+        Piece::Third => board.third_mask |= bit,
+        Piece::Fourth => board.fourth_mask |= bit,
+        Piece::Empty => {}
+    }
+}
+
+// checks every cell and direction one by one (columns wrap around on a cylinder)
+pub fn naive_check_win(board: &BitBoard, piece: Piece) -> bool {
+    let (rows, cols) = (board.rows as i32, board.cols as i32);
+    let wraps = board.variant.wraps();
+
+    for r in 0..rows {
+        for c in 0..cols {
+            for (dr, dc) in [(1, 0), (0, 1), (1, 1), (-1, 1)] {
+                let line = (0..board.connect as i32).all(|i| {
+                    let (rr, cc) = (r + i * dr, c + i * dc);
+                    let cc = if wraps { cc % cols } else { cc };
+                    rr >= 0 && rr < rows && cc < cols && board.get_piece(rr as u8, cc as u8) == piece
+                });
+                if line {
+                    return true;
+                }
+            }
+        }
+    }
+
+    false
+}
+
+// plays the columns alternately so that the last move is made by the player (the AI is to move)
+// returns None if a move is illegal or the game ended before the last move
+pub fn play_for_ai(board: &BitBoard, moves: &[u8]) -> Option<BitBoard> {
+    let mut piece = if moves.len() % 2 == 1 { Piece::Player } else { Piece::AI };
+    // on an empty board the first of the moves is the first of the game
+    let mut board = match board.occupied() {
+        0 => board.clone().with_first(piece),
+        _ => board.clone(),
+    };
+
+    for &col in moves {
+        if board.check_win(Piece::Player) || board.check_win(Piece::AI) {
+            return None;
+        }
+        board = board.drop_piece(col, piece)?;
+        piece = other(piece);
+    }
+
+    Some(board)
+}
+
+// exact game value for the AI to move: 1 win, 0 draw, -1 loss (plain negamax, only for tiny boards)
+pub fn solve(board: &BitBoard, to_move: Piece) -> i32 {
+    // the last move made a line - it wins, or loses in misère
+    if naive_check_win(board, other(to_move)) {
+        return if board.misere { 1 } else { -1 };
+    }
+    if board.is_full() {
+        return 0;
+    }
+
+    board.get_valid_locations()
+        .into_iter()
+        .map(|col| -solve(&board.drop_piece(col, to_move).unwrap(), other(to_move)))
+        .max()
+        .unwrap()
+}
+
+// plain alpha-beta over drops for the AI (maximizing) against the player, without table or move ordering -
+// the search the game used before Searcher, kept as a reference: (best column, score)
+pub fn minimax(board: &BitBoard, depth: u8, alpha: i32, beta: i32, is_maximizing: bool, params: &EvalParams) -> (Option<u8>, i32) {
+    let to_move = if is_maximizing { Piece::AI } else { Piece::Player };
+
+    if board.check_win(Piece::AI) {
+        return (None, WIN_SCORE);
+    } else if board.check_win(Piece::Player) {
+        return (None, -WIN_SCORE);
+    } else if board.is_full() {
+        return (None, 0);
+    }
+
+    if depth == 0 {
+        return (None, evaluate_heuristic(board, Piece::AI, to_move, params));
+    }
+
+    let (mut alpha, mut beta) = (alpha, beta);
+    let mut best_score = if is_maximizing { i32::MIN } else { i32::MAX };
+    let mut best_col = None;
+
+    for col in board.get_valid_locations() {
+        let new_board = board.drop_piece(col, to_move).unwrap();
+        let (_, score) = minimax(&new_board, depth - 1, alpha, beta, !is_maximizing, params);
+
+        if is_maximizing && score > best_score || !is_maximizing && score < best_score {
+            best_score = score;
+            best_col = Some(col);
+        }
+        match is_maximizing {
+            true => alpha = max(alpha, score),
+            false => beta = min(beta, score),
+        }
+        if alpha >= beta {
+            break;
+        }
+    }
+
+    (best_col, best_score)
+}
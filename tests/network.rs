@@ -0,0 +1,246 @@
+mod common;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::args;
+use connect_4::bitboard::{Move, Piece};
+use connect_4::net::{Connection, GameSetup, Message, NetError, NetGame, NetRole, PROTOCOL_VERSION};
+use connect_4::variant::Variant;
+
+fn setup() -> GameSetup {
+    GameSetup { rows: 6, cols: 7, connect: 4, variant: Variant::Classic, misere: false, blocked: Vec::new(), host_starts: true }
+}
+
+fn listener() -> (TcpListener, String) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    (listener, address)
+}
+
+// a raw client that speaks the protocol line by line
+struct Raw {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Raw {
+    fn connect(address: &str) -> Self {
+        let stream = TcpStream::connect(address).unwrap();
+        Self { reader: BufReader::new(stream.try_clone().unwrap()), writer: stream }
+    }
+
+    fn send(&mut self, line: &str) {
+        writeln!(self.writer, "{}", line).unwrap();
+    }
+
+    fn read(&mut self) -> String {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).unwrap();
+        line.trim().to_string()
+    }
+}
+
+#[test]
+fn messages_round_trip() {
+    let with_blockers = GameSetup { variant: Variant::PopOut, misere: true, blocked: vec![(0, 3), (2, 5)], host_starts: false, ..setup() };
+    let messages = [
+        Message::Hello(PROTOCOL_VERSION, 0),
+        Message::Hello(PROTOCOL_VERSION, u64::MAX),
+        Message::Settings(setup()),
+        Message::Settings(with_blockers),
+        Message::Moves(vec![]),
+        Message::Moves(vec![Move::Drop(3), Move::Pop(0)]),
+        Message::Move(7, Move::Pop(2)),
+        Message::Resync,
+        Message::Error("Illegal move 9 for Player".to_string()),
+        Message::Bye,
+    ];
+    for message in messages {
+        assert_eq!(message.to_string().parse::<Message>(), Ok(message.clone()), "{}", message);
+    }
+
+    assert_eq!("HELLO connect-4 1".parse(), Ok(Message::Hello(1, 0)));
+    assert_eq!("SETTINGS variant=classic connect=4 cols=7 rows=6 colour=red".parse(), Ok(Message::Settings(setup())));
+    for bad in ["", "HELLO other 1", "HELLO connect-4 2 x", "MOVE x 3", "MOVE 1", "MOVES 3 q", "SETTINGS rows=6 cols=40 connect=4", "SETTINGS rows=6 cols=7 connect=9", "SETTINGS rows=6 cols=7 connect=4 variant=connect-4x4"] {
+        assert!(bad.parse::<Message>().is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn moves_are_validated() {
+    let mut game = NetGame::new(GameSetup { rows: 2, cols: 2, connect: 2, ..setup() });
+    game.play(Move::Drop(0)).unwrap();
+    game.play(Move::Drop(0)).unwrap();
+    assert!(game.play(Move::Drop(0)).is_err());
+    assert!(game.play(Move::Drop(5)).is_err());
+    assert!(game.play(Move::Pop(1)).is_err());
+    assert_eq!((game.moves.len(), game.to_move), (2, Piece::Player));
+
+    game.play(Move::Drop(1)).unwrap();
+    assert_eq!(game.winner(), Some(Piece::Player));
+    assert!(game.is_over());
+    assert!(game.play(Move::Drop(1)).is_err());
+
+    assert!(NetGame::replay(setup(), &[Move::Drop(3), Move::Drop(8)]).is_err());
+}
+
+#[test]
+fn game_over_localhost() {
+    let (listener, address) = listener();
+    let blocked = GameSetup { blocked: vec![(0, 6)], ..setup() };
+    let host_setup = blocked.clone();
+
+    // the host plays column 0 four times, the guest column 1
+    let host = thread::spawn(move || {
+        let mut game = NetGame::new(host_setup);
+        let mut connection = Connection::accept(&listener, &game).unwrap();
+        while !game.is_over() {
+            game.play(Move::Drop(0)).unwrap();
+            connection.send_move(&game).unwrap();
+            connection.receive_move(&mut game).unwrap();
+        }
+        game
+    });
+
+    let (mut connection, mut game) = Connection::join(address.as_str()).unwrap();
+    assert_eq!((connection.me, &game.setup), (Piece::AI, &blocked));
+    assert!(game.board.is_blocked(0, 6));
+    loop {
+        connection.receive_move(&mut game).unwrap();
+        if game.is_over() {
+            break;
+        }
+        game.play(Move::Drop(1)).unwrap();
+        connection.send_move(&game).unwrap();
+    }
+
+    let host = host.join().unwrap();
+    assert_eq!(game.winner(), Some(Piece::Player));
+    assert_eq!(host.moves, game.moves);
+    assert_eq!(host.board.position_key(), game.board.position_key());
+}
+
+#[test]
+fn illegal_moves_and_other_versions_are_refused() {
+    let (listener, address) = listener();
+    let host = thread::spawn(move || {
+        let mut game = NetGame::new(GameSetup { host_starts: false, ..setup() });
+        let first = Connection::accept(&listener, &game).err();
+        let mut connection = Connection::accept(&listener, &game).unwrap();
+        (first, connection.receive_move(&mut game).err())
+    });
+
+    let mut old = Raw::connect(&address);
+    old.send("HELLO connect-4 0");
+    assert!(old.read().starts_with("ERROR"));
+
+    let mut guest = Raw::connect(&address);
+    guest.send(&Message::Hello(PROTOCOL_VERSION, 0).to_string());
+    assert!(guest.read().starts_with(&format!("HELLO connect-4 {} ", PROTOCOL_VERSION)));
+    assert!(guest.read().starts_with("SETTINGS"));
+    assert_eq!(guest.read(), "MOVES");
+
+    // a move with the wrong number is answered with the whole game
+    guest.send("MOVE 3 1");
+    assert_eq!(guest.read(), "MOVES");
+    guest.send("MOVE 0 9");
+    assert!(guest.read().starts_with("ERROR Illegal move 9"));
+
+    let (first, second) = host.join().unwrap();
+    assert!(matches!(first, Some(NetError::Protocol(_))));
+    assert!(matches!(second, Some(NetError::Protocol(_))));
+}
+
+#[test]
+fn guest_resyncs_after_reconnecting() {
+    let (listener, address) = listener();
+    let host = thread::spawn(move || {
+        let mut game = NetGame::new(setup());
+        let mut connection = Connection::accept(&listener, &game).unwrap();
+        game.play(Move::Drop(3)).unwrap();
+        connection.send_move(&game).unwrap();
+        connection.receive_move(&mut game).unwrap();
+        game.play(Move::Drop(2)).unwrap();
+
+        // the guest is gone before it gets the move - it comes back and gets the whole game
+        let lost = connection.receive_move(&mut game).unwrap_err();
+        let mut connection = Connection::accept_again(&listener, &game, Instant::now() + Duration::from_secs(10)).unwrap();
+        connection.receive_move(&mut game).unwrap();
+        (lost, game)
+    });
+
+    let (mut connection, mut game) = Connection::join(address.as_str()).unwrap();
+    connection.receive_move(&mut game).unwrap();
+    game.play(Move::Drop(4)).unwrap();
+    connection.send_move(&game).unwrap();
+    drop(connection);
+
+    let (mut connection, mut game) = Connection::join_again(address.as_str(), &game).unwrap();
+    assert_eq!(game.moves, vec![Move::Drop(3), Move::Drop(4), Move::Drop(2)]);
+    assert_eq!(game.to_move, Piece::AI);
+
+    game.play(Move::Drop(5)).unwrap();
+    connection.send_move(&game).unwrap();
+
+    let (lost, host_game) = host.join().unwrap();
+    assert!(matches!(lost, NetError::Io(_)));
+    assert_eq!(host_game.moves, game.moves);
+}
+
+#[test]
+fn only_the_guest_of_the_game_can_come_back() {
+    let (listener, address) = listener();
+    let game = NetGame::new(setup());
+    let id = game.id;
+    let host = thread::spawn(move || {
+        let connection = Connection::accept_again(&listener, &game, Instant::now() + Duration::from_secs(10));
+        connection.map(|connection| connection.me)
+    });
+
+    // a new guest and a wrong id are turned away, the host keeps waiting
+    let mut stranger = Raw::connect(&address);
+    stranger.send(&Message::Hello(PROTOCOL_VERSION, 0).to_string());
+    assert!(stranger.read().starts_with("ERROR"));
+    let mut guesser = Raw::connect(&address);
+    guesser.send(&Message::Hello(PROTOCOL_VERSION, id.wrapping_add(1)).to_string());
+    assert!(guesser.read().starts_with("ERROR"));
+
+    let mut guest = Raw::connect(&address);
+    guest.send(&Message::Hello(PROTOCOL_VERSION, id).to_string());
+    assert_eq!(guest.read(), Message::Hello(PROTOCOL_VERSION, id).to_string());
+    assert_eq!(host.join().unwrap().unwrap(), Piece::Player);
+}
+
+#[test]
+fn the_host_stops_waiting_at_the_deadline() {
+    let (listener, _) = listener();
+    let game = NetGame::new(setup());
+    let started = Instant::now();
+    let result = Connection::accept_again(&listener, &game, started + Duration::from_millis(200));
+    assert!(matches!(result, Err(NetError::Io(_))));
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    // the listener still works after the timeout
+    let address = listener.local_addr().unwrap().to_string();
+    let host = thread::spawn(move || Connection::accept(&listener, &game).map(|c| c.me).ok());
+    let (_, joined) = Connection::join(address.as_str()).unwrap();
+    assert_ne!(joined.id, 0);
+    assert_eq!(host.join().unwrap(), Some(Piece::Player));
+}
+
+#[test]
+fn network_options() {
+    assert_eq!(args(&["--host", "4000"]).unwrap().network, Some(NetRole::Host(4000)));
+    assert_eq!(args(&["--join", "localhost:4000"]).unwrap().network, Some(NetRole::Join("localhost:4000".to_string())));
+    assert!(args(&["--host", "port"]).is_err());
+    assert!(args(&["--host", "4000", "--players", "3"]).is_err());
+    assert!(args(&["--host", "4000", "--variant", "connect-4x4"]).is_err());
+
+    assert_eq!(args(&["--host", "4000", "--bind", "127.0.0.1"]).unwrap().bind, Some("127.0.0.1".parse().unwrap()));
+    assert_eq!(args(&["--host", "4000"]).unwrap().bind, None);
+    assert!(args(&["--host", "4000", "--bind", "localhost:4000"]).is_err());
+    assert!(args(&["--join", "localhost:4000", "--bind", "127.0.0.1"]).is_err());
+}
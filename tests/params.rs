@@ -0,0 +1,21 @@
+use connect_4::minimax::WIN_SCORE;
+use connect_4::params::{EvalParams, MAX_WEIGHT};
+
+#[test]
+fn weights_out_of_range_are_refused() {
+    let mut params = EvalParams::default();
+    params.set("weight_missing_one", &MAX_WEIGHT.to_string()).unwrap();
+    params.set("center_weight", &(-MAX_WEIGHT).to_string()).unwrap();
+    assert_eq!(params.weight_missing_one, MAX_WEIGHT);
+
+    // a product with a count of sequences could reach the score of a win
+    let err = params.set("weight_missing_one", &(MAX_WEIGHT + 1).to_string()).unwrap_err();
+    assert!(err.contains("weight_missing_one must be between"), "{}", err);
+    assert_eq!(params.weight_missing_one, MAX_WEIGHT);
+    assert!(params.set("win_score", &WIN_SCORE.to_string()).is_err());
+    assert!(params.set("win_score", &(WIN_SCORE - 1).to_string()).is_ok());
+
+    assert!(EvalParams::from_toml("immediate_threat = 2000000000").is_err());
+    assert!(EvalParams::from_toml("immediate_threat = 150000").is_ok());
+    assert!(EvalParams::from_toml(&EvalParams::default().to_toml()).is_ok());
+}
@@ -0,0 +1,142 @@
+mod common;
+
+use common::{args, naive_check_win};
+use connect_4::bitboard::{BitBoard, Piece};
+use connect_4::params::EvalParams;
+use connect_4::search::Searcher;
+use connect_4::variant::Variant;
+use proptest::prelude::*;
+
+// drops the pieces one after another, without checking whose turn it is
+fn drops(board: BitBoard, moves: &[(u8, Piece)]) -> BitBoard {
+    moves.iter().fold(board, |board, &(col, piece)| board.drop_piece(col, piece).unwrap())
+}
+
+#[test]
+fn turns_rotate_through_the_colours() {
+    let board = BitBoard::new(6, 7, 4).with_players(3);
+    assert_eq!(board.colours(), &[Piece::Player, Piece::AI, Piece::Third]);
+    assert_eq!(board.next_player(Piece::AI), Piece::Third);
+    assert_eq!(board.next_player(Piece::Third), Piece::Player);
+    assert_eq!(board.previous_player(Piece::Player), Piece::Third);
+
+    let board = board.with_players(4);
+    assert_eq!(board.next_player(Piece::Third), Piece::Fourth);
+    assert_eq!(board.next_player(Piece::Fourth), Piece::Player);
+
+    let two = BitBoard::new(6, 7, 4);
+    assert_eq!(two.next_player(Piece::AI), Piece::Player);
+    assert_eq!(two.previous_player(Piece::Player), Piece::AI);
+}
+
+#[test]
+fn player_count_follows_the_variant() {
+    assert_eq!(BitBoard::new(6, 7, 4).with_variant(Variant::PopOut).with_players(3).players, 2);
+    assert_eq!(BitBoard::new(6, 7, 4).with_players(3).with_variant(Variant::PopOut).players, 2);
+    assert_eq!(BitBoard::new(6, 10, 4).with_variant(Variant::Connect4x4).players, 4);
+    assert!(!BitBoard::new(6, 7, 4).with_players(3).with_misere(true).misere);
+
+    assert_eq!(args(&["--players", "3"]).unwrap().players, 3);
+    assert_eq!(args(&[]).unwrap().players, 2);
+    assert!(args(&["--players", "5"]).is_err());
+    assert!(args(&["--players", "3", "--pop-out"]).is_err());
+    assert!(args(&["--players", "2", "--variant", "connect-4x4"]).is_err());
+    assert!(args(&["--players", "3", "--misere"]).is_err());
+}
+
+#[test]
+fn every_colour_has_its_own_pieces_and_lines() {
+    let board = drops(BitBoard::new(6, 7, 4).with_players(4), &[(0, Piece::Third), (0, Piece::Fourth), (1, Piece::Third)]);
+
+    assert_eq!(board.get_piece(0, 0), Piece::Third);
+    assert_eq!(board.get_piece(1, 0), Piece::Fourth);
+    assert_eq!(board.mask(Piece::Third).count_ones(), 2);
+    assert_eq!(board.occupied().count_ones(), 3);
+    assert_eq!(board.height(0), 2);
+
+    let board = drops(board, &[(2, Piece::Third), (3, Piece::Third)]);
+    assert!(board.check_win(Piece::Third));
+    assert!(!board.check_win(Piece::Fourth));
+    assert_eq!(board.winner(Piece::Third), Some(Piece::Third));
+    // the line is found whoever moved last
+    assert_eq!(board.winner(Piece::Player), Some(Piece::Third));
+
+    let text = board.to_string();
+    assert!(text.contains('+') && text.contains('*'));
+}
+
+#[test]
+fn teams_share_a_win() {
+    let board = BitBoard::new(6, 10, 4).with_variant(Variant::Connect4x4);
+    assert!(board.same_team(Piece::Player, Piece::Third));
+    assert!(board.same_team(Piece::AI, Piece::Fourth));
+    assert!(!board.same_team(Piece::Player, Piece::AI));
+    assert!(!BitBoard::new(6, 7, 4).with_players(4).same_team(Piece::Player, Piece::Third));
+}
+
+#[test]
+fn paranoid_search_blocks_any_opponent() {
+    // the third colour has three stacked in column 5 and moves after the AI
+    let board = drops(BitBoard::new(6, 7, 4).with_players(3), &[
+        (5, Piece::Third), (0, Piece::Player), (5, Piece::Third), (6, Piece::Player), (5, Piece::Third), (1, Piece::AI),
+    ]);
+
+    let info = Searcher::new(EvalParams::default()).search_for(&board, Piece::AI, 3, None, None);
+    assert_eq!(info.best_move.map(|mv| mv.col()), Some(5), "{}", board);
+
+    // and the third colour takes its win when it is to move
+    let info = Searcher::new(EvalParams::default()).search_for(&board, Piece::Third, 3, None, None);
+    assert_eq!(info.best_move.map(|mv| mv.col()), Some(5));
+    assert!(info.score >= 100_000_000);
+}
+
+#[test]
+fn search_leaves_a_teammate_line_alone() {
+    // Connect 4x4: the fourth colour (the AI's teammate) has three in column 2, the human team
+    // cannot stop it in time - the AI does not have to block it and scores the position as won
+    let board = drops(BitBoard::new(6, 10, 4).with_variant(Variant::Connect4x4), &[
+        (2, Piece::Fourth), (2, Piece::Fourth), (2, Piece::Fourth), (7, Piece::Player), (8, Piece::Third),
+    ]);
+
+    let info = Searcher::new(EvalParams::default()).search_for(&board, Piece::AI, 4, None, None);
+    assert_ne!(info.best_move.map(|mv| mv.col()), Some(2));
+}
+
+#[test]
+fn ai_only_games_finish() {
+    for players in [3, 4] {
+        let mut board = BitBoard::new(5, 6, 4).with_players(players);
+        let mut searcher = Searcher::new(EvalParams::default());
+        let mut current = Piece::Player;
+
+        loop {
+            let info = searcher.search_for(&board, current, 2, None, None);
+            board = board.play(info.best_move.expect("a move while the game runs"), current).unwrap();
+            if board.winner(current).is_some() || !board.has_legal_move(board.next_player(current)) {
+                break;
+            }
+            current = board.next_player(current);
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    fn random_multiplayer_games_match_naive_reference(players in 3..=4u8, moves in prop::collection::vec(any::<usize>(), 0..42)) {
+        let mut board = BitBoard::new(6, 7, 4).with_players(players);
+        let mut piece = Piece::Player;
+
+        for choice in moves {
+            let valid = board.get_valid_locations();
+            if valid.is_empty() {
+                break;
+            }
+            board = board.drop_piece(valid[choice % valid.len()], piece).unwrap();
+            piece = board.next_player(piece);
+
+            for &colour in board.colours() {
+                prop_assert_eq!(board.check_win(colour), naive_check_win(&board, colour));
+            }
+        }
+    }
+}
@@ -0,0 +1,104 @@
+mod common;
+
+use std::time::Duration;
+
+use common::args;
+use connect_4::bitboard::{BitBoard, Move, Piece};
+use connect_4::params::EvalParams;
+use connect_4::replay::Replay;
+use connect_4::search::Searcher;
+use connect_4::stats::{GameRecord, GameResult, Stats};
+use connect_4::variant::Variant;
+
+// the AI starts and wins with four in column 0, the player wastes moves in column 1
+fn vertical_win() -> GameRecord {
+    let moves = [0, 1, 0, 1, 0, 1, 0].map(Move::Drop);
+    let board = moves.iter().enumerate().fold(BitBoard::new(6, 7, 4).with_blockers(&[(0, 6)]), |board, (i, &mv)| {
+        board.play(mv, if i % 2 == 0 { Piece::AI } else { Piece::Player }).unwrap()
+    });
+    GameRecord::new(&board, Piece::AI, &moves, 4, GameResult::Loss, Duration::from_secs(30))
+}
+
+#[test]
+fn steps_through_the_recorded_moves() {
+    let mut replay = Replay::new(&vertical_win()).unwrap();
+    assert_eq!((replay.len(), replay.position, replay.to_move()), (7, 0, Piece::AI));
+    assert_eq!(replay.board().occupied(), 0);
+    assert!(replay.board().is_blocked(0, 6));
+    assert_eq!(replay.describe(), "Start (7 moves)");
+
+    replay.forward();
+    replay.forward();
+    assert_eq!(replay.last_move(), Some((2, Piece::Player, Move::Drop(1))));
+    assert_eq!(replay.describe(), "Move 2/7: Player drops into column 1");
+    assert_eq!((replay.board().get_piece(0, 0), replay.to_move()), (Piece::AI, Piece::AI));
+
+    replay.back();
+    assert_eq!((replay.position, replay.board().get_piece(0, 1)), (1, Piece::Empty));
+    replay.back();
+    replay.back();
+    assert_eq!(replay.position, 0);
+
+    replay.go_to(99);
+    assert_eq!(replay.position, 7);
+    assert!(replay.board().check_win(Piece::AI));
+    replay.forward();
+    assert_eq!(replay.position, 7);
+}
+
+#[test]
+fn engine_evaluates_each_step() {
+    let mut replay = Replay::new(&vertical_win()).unwrap();
+    let mut searcher = Searcher::new(EvalParams::default());
+
+    // before the last move the AI wins in one
+    replay.go_to(6);
+    let info = replay.evaluate(&mut searcher, 4).unwrap();
+    assert_eq!((info.best_move, replay.to_move()), (Some(Move::Drop(0)), Piece::AI));
+    assert!(info.score >= 100_000_000);
+
+    // the stepped evaluation of the GUI comes to the same move
+    let mut search = replay.evaluation(4).unwrap();
+    let stepped = loop {
+        if let Some(info) = search.step(&mut searcher, None, None) {
+            break info;
+        }
+    };
+    assert_eq!((stepped.best_move, stepped.score), (info.best_move, info.score));
+
+    replay.go_to(7);
+    assert!(replay.evaluate(&mut searcher, 4).is_none());
+    assert!(replay.evaluation(4).is_none());
+    assert_eq!(searcher.time_limit, None);
+}
+
+#[test]
+fn games_are_picked_from_the_stats() {
+    let mut older = vertical_win();
+    older.history = "3 3".to_string();
+    older.moves = 2;
+    older.variant = Variant::PopOut.name().to_string();
+    let stats = Stats { games: vec![older, vertical_win()] };
+
+    assert_eq!(Replay::from_stats(&stats, None).unwrap().len(), 7);
+    let first = Replay::from_stats(&stats, Some(1)).unwrap();
+    assert_eq!((first.len(), first.board().variant), (2, Variant::PopOut));
+    assert!(Replay::from_stats(&stats, Some(0)).is_err());
+    assert!(Replay::from_stats(&stats, Some(3)).is_err());
+    assert!(Replay::from_stats(&Stats::default(), None).is_err());
+
+    let mut broken = vertical_win();
+    broken.history = "0 0 0 0 0 0 0".to_string();
+    assert!(Replay::new(&broken).err().unwrap().contains("Illegal move 0"));
+    broken.history.clear();
+    assert!(Replay::new(&broken).is_err());
+}
+
+#[test]
+fn replay_command() {
+    assert_eq!(args(&["replay"]).unwrap().replay, Some(None));
+    assert_eq!(args(&["replay", "3", "--ponder"]).unwrap().replay, Some(Some(3)));
+    let before_option = args(&["replay", "--ponder"]).unwrap();
+    assert_eq!((before_option.replay, before_option.ponder), (Some(None), true));
+    assert_eq!(args(&[]).unwrap().replay, None);
+}
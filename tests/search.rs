@@ -0,0 +1,364 @@
+mod common;
+
+use common::{minimax, play_for_ai, solve};
+use connect_4::bitboard::{BitBoard, Move, Piece};
+use connect_4::variant::Variant;
+use connect_4::engine::{Engine, SteppedSearch};
+use connect_4::minimax::{best_move, TieBreak};
+use connect_4::params::EvalParams;
+use connect_4::search::{SearchInfo, Searcher};
+use proptest::prelude::*;
+
+const WIN: i32 = 100_000_000;
+
+struct Position {
+    line: usize,
+    board: BitBoard,
+    depth: u8,
+    best: Option<Vec<u8>>,
+    result: Option<i32>,
+}
+
+fn parse_list(field: &str) -> Vec<u8> {
+    field.split_whitespace().map(|c| c.parse().unwrap()).collect()
+}
+
+fn load_positions() -> Vec<Position> {
+    let text = include_str!("data/positions.txt");
+
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| {
+            let fields: Vec<&str> = line.split('|').map(str::trim).collect();
+            assert_eq!(fields.len(), 5, "line {}: expected 5 fields", i + 1);
+
+            let size = parse_list(fields[0]);
+            let board = BitBoard::new(size[0], size[1], size[2]);
+            let board = play_for_ai(&board, &parse_list(fields[1]))
+                .unwrap_or_else(|| panic!("line {}: illegal moves", i + 1));
+
+            Position {
+                line: i + 1,
+                board,
+                depth: fields[2].parse().unwrap(),
+                best: (fields[3] != "-").then(|| parse_list(fields[3])),
+                result: match fields[4] {
+                    "win" => Some(1),
+                    "draw" => Some(0),
+                    "loss" => Some(-1),
+                    _ => None,
+                },
+            }
+        })
+        .collect()
+}
+
+// the corpus lists columns, the positions are classic so the best move is always a drop
+fn is_one_of(best: &[u8], mv: Move) -> bool {
+    best.iter().any(|&col| Move::Drop(col) == mv)
+}
+
+fn result_of(score: i32) -> i32 {
+    match score {
+        s if s >= WIN => 1,
+        s if s <= -WIN => -1,
+        _ => 0,
+    }
+}
+
+#[test]
+fn known_positions() {
+    let params = EvalParams::default();
+    let positions = load_positions();
+    assert!(!positions.is_empty());
+
+    for p in positions {
+        let (col, score) = minimax(&p.board, p.depth, i32::MIN, i32::MAX, true, &params);
+        let col = col.expect("a move must be found");
+
+        if let Some(best) = &p.best {
+            assert!(best.contains(&col), "line {}: played {}, expected one of {:?}{}", p.line, col, best, p.board);
+        }
+        if let Some(result) = p.result {
+            assert_eq!(result_of(score), result, "line {}: score {}{}", p.line, score, p.board);
+        }
+    }
+}
+
+// depth 0 is scored for the AI, the maximizing side, whoever is to move - it used to be scored for the
+// side to move, so a leaf with the player to move had the wrong sign
+#[test]
+fn minimax_scores_leaves_for_the_ai() {
+    let params = EvalParams::default();
+    // the AI has an open three on the bottom row, the player's pieces are out of the way in column 6
+    let ai_ahead = [(1, Piece::AI), (6, Piece::Player), (2, Piece::AI), (6, Piece::Player), (3, Piece::AI)].iter()
+        .fold(BitBoard::new(6, 7, 4), |board, &(col, piece)| board.drop_piece(col, piece).unwrap());
+
+    let (_, player_to_move) = minimax(&ai_ahead, 0, i32::MIN, i32::MAX, false, &params);
+    let (_, ai_to_move) = minimax(&ai_ahead, 0, i32::MIN, i32::MAX, true, &params);
+    assert!(player_to_move > 0 && ai_to_move > 0, "{} {}", player_to_move, ai_to_move);
+}
+
+#[test]
+fn known_positions_with_tie_breaking() {
+    let params = EvalParams::default();
+
+    for p in load_positions() {
+        let mut tie_break = TieBreak::new(p.line as u64, 50);
+        let (col, score) = best_move(&p.board, p.depth, &params, Some(&mut tie_break));
+
+        if let Some(best) = &p.best {
+            assert!(is_one_of(best, col.unwrap()), "line {}{}", p.line, p.board);
+        }
+        if let Some(result) = p.result {
+            assert_eq!(result_of(score), result, "line {}", p.line);
+        }
+    }
+}
+
+#[test]
+fn known_positions_with_searcher() {
+    // one searcher for all positions, the table must not leak between board sizes
+    let mut searcher = Searcher::new(EvalParams::default());
+
+    for p in load_positions() {
+        let info = searcher.search(&p.board, p.depth, None, None);
+
+        if let Some(best) = &p.best {
+            assert!(is_one_of(best, info.best_move.unwrap()), "line {}{}", p.line, p.board);
+        }
+        if let Some(result) = p.result {
+            assert_eq!(result_of(info.score), result, "line {}", p.line);
+        }
+    }
+}
+
+#[test]
+fn progress_is_reported_for_every_depth() {
+    let board = play_for_ai(&BitBoard::new(6, 7, 4), &[3, 3, 2]).unwrap();
+    let mut searcher = Searcher::new(EvalParams::default());
+    let mut progress: Vec<SearchInfo> = Vec::new();
+
+    let info = searcher.search(&board, 6, None, Some(&mut |info: &SearchInfo| progress.push(info.clone())));
+
+    let depths: Vec<u8> = progress.iter().map(|i| i.depth).collect();
+    assert_eq!(depths, vec![1, 2, 3, 4, 5, 6]);
+    assert!(progress.windows(2).all(|w| w[0].nodes <= w[1].nodes));
+
+    let last = progress.last().unwrap();
+    assert_eq!((last.best_move, last.score, last.nodes), (info.best_move, info.score, info.nodes));
+    assert!(info.nodes > 0 && info.cutoffs > 0);
+
+    // the principal variation starts with the chosen move and is playable
+    assert_eq!(info.pv.first().copied(), info.best_move);
+    assert!(info.pv.len() <= 6);
+    let mut replay = board.clone();
+    let mut piece = Piece::AI;
+    for &mv in &info.pv {
+        replay = replay.play(mv, piece).expect("legal pv move");
+        piece = if piece == Piece::AI { Piece::Player } else { Piece::AI };
+    }
+}
+
+#[test]
+fn table_is_reused_between_searches() {
+    let board = play_for_ai(&BitBoard::new(6, 7, 4), &[3]).unwrap();
+    let mut searcher = Searcher::new(EvalParams::default());
+
+    let first = searcher.search(&board, 7, None, None);
+    let second = searcher.search(&board, 7, None, None);
+
+    assert!(second.tt_hits > 0);
+    assert!(second.nodes < first.nodes);
+    assert_eq!(second.best_move, first.best_move);
+}
+
+#[test]
+fn table_stays_within_its_limit_during_a_search() {
+    let mut searcher = Searcher::new(EvalParams::default());
+    searcher.table_limit = 1000;
+    let info = searcher.search(&BitBoard::new(6, 7, 4), 7, None, None);
+    assert!(info.best_move.is_some() && info.nodes > 5_000);
+    assert!(searcher.table_len() <= 1000, "{}", searcher.table_len());
+}
+
+#[test]
+fn pondering_speeds_up_the_reply() {
+    // human to move
+    let board = BitBoard::new(6, 7, 4).drop_piece(3, Piece::Player).unwrap().drop_piece(3, Piece::AI).unwrap();
+    let reply = board.drop_piece(2, Piece::Player).unwrap();
+
+    let cold = Searcher::new(EvalParams::default()).search(&reply, 7, None, None);
+
+    let mut searcher = Searcher::new(EvalParams::default());
+    assert_eq!(searcher.ponder(&board, 8), 8);
+    let warm = searcher.search(&reply, 7, None, None);
+
+    assert!(warm.nodes < cold.nodes, "warm {} cold {}", warm.nodes, cold.nodes);
+    assert!(warm.tt_hits > 0);
+}
+
+#[test]
+fn engine_can_be_stopped_while_pondering() {
+    let board = BitBoard::new(6, 7, 4).drop_piece(3, Piece::Player).unwrap().drop_piece(3, Piece::AI).unwrap();
+    let mut engine = Engine::new(Searcher::new(EvalParams::default()), true);
+
+    engine.start_pondering(&board, 20);
+    assert!(engine.is_pondering());
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    engine.stop_pondering();
+    assert!(!engine.is_pondering());
+
+    // the stop flag is reset, the next search runs to the requested depth
+    let reply = board.drop_piece(2, Piece::Player).unwrap();
+    let info = engine.searcher().search(&reply, 5, None, None);
+    assert_eq!(info.depth, 5);
+    assert!(info.best_move.is_some());
+}
+
+#[test]
+fn time_limit_ends_the_search() {
+    let mut searcher = Searcher::new(EvalParams::default());
+    searcher.time_limit = Some(std::time::Duration::from_millis(50));
+
+    let start = std::time::Instant::now();
+    let info = searcher.search(&BitBoard::new(6, 7, 4), 40, None, None);
+    assert!(start.elapsed() < std::time::Duration::from_secs(2));
+    assert!(info.depth >= 1 && info.depth < 40);
+    assert!(info.best_move.is_some());
+
+    // the limit is per search, the next one starts with a fresh clock
+    let info = searcher.search(&BitBoard::new(4, 4, 3), 3, None, None);
+    assert_eq!(info.depth, 3);
+}
+
+#[test]
+fn stepped_search_matches_a_plain_search() {
+    let board = play_for_ai(&BitBoard::new(6, 7, 4), &[3, 3, 2, 4]).unwrap();
+    let plain = Searcher::new(EvalParams::default()).search(&board, 9, None, None);
+
+    let mut searcher = Searcher::new(EvalParams::default());
+    let mut search = SteppedSearch::new(&board, Piece::AI, 9);
+    let mut steps = 0;
+    let mut depths = Vec::new();
+    let info = loop {
+        // every step is short, not only the first ones
+        let start = std::time::Instant::now();
+        let info = search.step(&mut searcher, None, Some(&mut |info: &SearchInfo| depths.push(info.depth)));
+        assert!(start.elapsed() < std::time::Duration::from_millis(200), "step {} took {:?}", steps, start.elapsed());
+        steps += 1;
+        if let Some(info) = info {
+            break info;
+        }
+    };
+
+    assert!(steps > 1);
+    assert_eq!(searcher.time_limit, None);
+    assert_eq!((info.best_move, info.score, info.depth), (plain.best_move, plain.score, plain.depth));
+    // every depth is reported once, a step goes on where the last one stopped
+    assert_eq!(depths, (1..=9).collect::<Vec<_>>());
+}
+
+#[test]
+fn tie_breaking_is_reproducible_from_the_seed() {
+    let params = EvalParams::default();
+    let board = BitBoard::new(6, 7, 4);
+
+    let play = |seed| {
+        let mut tie_break = TieBreak::new(seed, 1000);
+        (0..5).map(|_| best_move(&board, 3, &params, Some(&mut tie_break)).0.unwrap()).collect::<Vec<_>>()
+    };
+
+    assert_eq!(play(7), play(7));
+}
+
+// small boards where a search to the end is cheap, with the exact value from a plain negamax
+fn small_position() -> impl Strategy<Value = BitBoard> {
+    let sizes = prop::sample::select(vec![(2u8, 2u8, 2u8), (3, 3, 3), (3, 4, 3), (4, 3, 3), (4, 4, 3), (3, 4, 2)]);
+
+    (sizes, prop::collection::vec(any::<usize>(), 4..10)).prop_filter_map("game over", |((rows, cols, connect), choices)| {
+        let mut board = BitBoard::new(rows, cols, connect);
+        let mut moves = Vec::new();
+
+        for choice in choices {
+            let valid = board.get_valid_locations();
+            if valid.is_empty() {
+                return None;
+            }
+            let col = valid[choice % valid.len()];
+            moves.push(col);
+            board = board.drop_piece(col, Piece::Player).unwrap();
+        }
+
+        // keep an odd number of moves, so the player moved last
+        if moves.len() % 2 == 0 {
+            moves.pop();
+        }
+        let board = play_for_ai(&BitBoard::new(rows, cols, connect), &moves)?;
+        if board.check_win(Piece::Player) || board.check_win(Piece::AI) || board.is_full() {
+            return None;
+        }
+        Some(board)
+    })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn full_depth_search_matches_solver(board in small_position()) {
+        let params = EvalParams::default();
+        let empty = (board.rows as u32 * board.cols as u32 - board.player_mask.count_ones() - board.ai_mask.count_ones()) as u8;
+
+        let expected = solve(&board, Piece::AI);
+        let (col, score) = minimax(&board, empty, i32::MIN, i32::MAX, true, &params);
+        prop_assert_eq!(result_of(score), expected, "{}", board);
+
+        // the chosen move keeps the value
+        let after = board.drop_piece(col.unwrap(), Piece::AI).unwrap();
+        prop_assert_eq!(-solve(&after, Piece::Player), expected, "{}", board);
+
+        let info = Searcher::new(params).search(&board, empty, None, None);
+        prop_assert_eq!(result_of(info.score), expected, "{}", board);
+        let after = board.play(info.best_move.unwrap(), Piece::AI).unwrap();
+        prop_assert_eq!(-solve(&after, Piece::Player), expected, "{}", board);
+
+        // analyze scores every move exactly
+        let info = Searcher::new(EvalParams::default()).analyze(&board, Piece::AI, empty);
+        prop_assert_eq!(info.move_scores.len(), board.get_valid_locations().len());
+        for (mv, score) in info.move_scores {
+            let after = board.play(mv, Piece::AI).unwrap();
+            prop_assert_eq!(result_of(score), -solve(&after, Piece::Player), "{} {}", board, mv);
+        }
+    }
+
+    #[test]
+    fn full_depth_misere_search_matches_solver(board in small_position()) {
+        let board = board.with_misere(true);
+        let empty = (board.rows as u32 * board.cols as u32 - board.player_mask.count_ones() - board.ai_mask.count_ones()) as u8;
+
+        let expected = solve(&board, Piece::AI);
+        let info = Searcher::new(EvalParams::default()).search(&board, empty, None, None);
+        prop_assert_eq!(result_of(info.score), expected, "{}", board);
+        let after = board.play(info.best_move.unwrap(), Piece::AI).unwrap();
+        prop_assert_eq!(-solve(&after, Piece::Player), expected, "{}", board);
+    }
+}
+
+#[test]
+fn searcher_finds_a_winning_pop() {
+    // the AI has three on the second row, popping its bottom piece in column 3 drops the
+    // player piece above it out of the way and completes the line below
+    let mut board = BitBoard::new(6, 7, 4).with_variant(Variant::PopOut);
+    for (col, pieces) in [(0, [Piece::Player, Piece::AI]), (1, [Piece::Player, Piece::AI]), (2, [Piece::Player, Piece::AI])] {
+        for piece in pieces {
+            board = board.drop_piece(col, piece).unwrap();
+        }
+    }
+    board = board.drop_piece(3, Piece::AI).unwrap().drop_piece(3, Piece::Player).unwrap().drop_piece(3, Piece::AI).unwrap();
+
+    let info = Searcher::new(EvalParams::default()).search(&board, 2, None, None);
+    assert_eq!(info.best_move, Some(Move::Pop(3)), "{}", board);
+    assert!(info.score >= WIN);
+}
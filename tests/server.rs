@@ -0,0 +1,127 @@
+#![cfg(feature = "server")]
+
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::thread;
+
+use connect_4::params::EvalParams;
+use connect_4::search::Searcher;
+use connect_4::server::{handle, serve};
+use serde_json::Value;
+
+fn post(path: &str, body: &str) -> (u16, Value) {
+    handle(&mut Searcher::new(EvalParams::default()), "POST", path, body)
+}
+
+#[test]
+fn analyze_scores_every_column() {
+    // X has three in column 0 and is to move again after O played column 6
+    let (status, value) = post("/analyze", r#"{"moves": [0, 1, 0, 1, 0, 6], "depth": 4}"#);
+    assert_eq!(status, 200, "{}", value);
+    assert_eq!(value["best_column"], 0);
+    assert_eq!(value["best_move"], "0");
+    assert_eq!(value["result"], "win");
+    assert_eq!(value["to_move"], "Player");
+    assert_eq!(value["pv"][0], "0");
+
+    let scores = value["scores"].as_array().unwrap();
+    assert_eq!(scores.len(), 7);
+    assert_eq!(scores.iter().map(|s| s["column"].as_u64().unwrap()).collect::<Vec<_>>(), (0..7).collect::<Vec<_>>());
+    let best = scores[0]["score"].as_i64().unwrap();
+    assert!(scores[1..].iter().all(|s| s["score"].as_i64().unwrap() < best));
+}
+
+#[test]
+fn move_plays_for_the_side_to_move() {
+    let (status, value) = post("/move", r#"{"rows": 4, "cols": 5, "connect": 3, "moves": ["1", 1, 2], "time_ms": 200}"#);
+    assert_eq!(status, 200, "{}", value);
+    assert_eq!(value["played_by"], "AI");
+    assert_eq!(value["moves"].as_array().unwrap().len(), 4);
+    // X threatens both ends of 1-2, the AI blocks one and X still wins next
+    assert_eq!(value["state"]["to_move"], "Player");
+    assert_eq!(value["state"]["game_over"], false);
+
+    // pops and variants
+    let (status, value) = post("/move", r#"{"variant": "pop-out", "moves": [3, 3], "depth": 2}"#);
+    assert_eq!(status, 200, "{}", value);
+}
+
+#[test]
+fn bad_requests_are_refused() {
+    for body in [
+        "not json",
+        r#"{"moves": [9]}"#,
+        r#"{"moves": ["p0"]}"#,
+        r#"{"rows": 6, "cols": 70}"#,
+        r#"{"connect": 9}"#,
+        r#"{"variant": "chess"}"#,
+        r#"{"players": 5}"#,
+        r#"{"colour": "red"}"#,
+    ] {
+        let (status, value) = post("/analyze", body);
+        assert_eq!(status, 400, "{}", body);
+        assert!(value["error"].is_string());
+    }
+
+    let (status, value) = post("/move", r#"{"moves": [0, 1, 0, 1, 0, 1, 0]}"#);
+    assert_eq!((status, &value["state"]["winner"]), (409, &Value::from("Player")));
+
+    let mut searcher = Searcher::new(EvalParams::default());
+    assert_eq!(handle(&mut searcher, "GET", "/analyze", "").0, 405);
+    assert_eq!(handle(&mut searcher, "POST", "/nothing", "{}").0, 404);
+    assert_eq!(handle(&mut searcher, "POST", "/nothing?/move", "{}").0, 404);
+}
+
+#[test]
+fn time_limit_is_kept() {
+    let start = std::time::Instant::now();
+    let (status, value) = post("/analyze", r#"{"depth": 30, "time_ms": 100}"#);
+    assert_eq!(status, 200);
+    assert!(start.elapsed() < std::time::Duration::from_secs(3));
+    assert!(value["depth"].as_u64().unwrap() < 30);
+}
+
+#[test]
+fn serves_http_on_localhost() {
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let address = server.server_addr().to_ip().unwrap();
+    thread::spawn(move || serve(server, EvalParams::default()));
+
+    let body = r#"{"moves": [3], "depth": 3}"#;
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(stream, "POST /move HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.to_lowercase().contains("content-type: application/json"));
+    let json: Value = serde_json::from_str(response.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+    assert_eq!(json["played_by"], "AI");
+}
+
+#[test]
+fn query_strings_and_large_bodies() {
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let address = server.server_addr().to_ip().unwrap();
+    thread::spawn(move || serve(server, EvalParams::default()));
+
+    let request = |head: &str, body: &str| {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "{}\r\nHost: localhost\r\nConnection: close\r\n\r\n{}", head, body).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+
+    let body = r#"{"moves": [3], "depth": 3}"#;
+    let response = request(&format!("POST /move?client=web HTTP/1.1\r\nContent-Length: {}", body.len()), body);
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+    // the announced length is refused before anything is read, a chunked body after the limit
+    let response = request("POST /move HTTP/1.1\r\nContent-Length: 100000000", body);
+    assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+    let chunk = " ".repeat(70 * 1024);
+    let response = request("POST /move HTTP/1.1\r\nTransfer-Encoding: chunked", &format!("{:x}\r\n{}\r\n0\r\n\r\n", chunk.len(), chunk));
+    assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+}
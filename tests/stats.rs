@@ -0,0 +1,102 @@
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use common::args;
+use connect_4::bitboard::{BitBoard, Move, Piece};
+use connect_4::stats::{level_rating, GameRecord, GameResult, Stats};
+use connect_4::variant::Variant;
+
+// a file of its own for every test, tests run in parallel
+fn stats_file(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("connect-4-stats-{}-{}.toml", name, std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+fn game(difficulty: u8, result: GameResult) -> GameRecord {
+    GameRecord::new(&BitBoard::new(6, 7, 4), Piece::Player, &[Move::Drop(3); 21], difficulty, result, Duration::from_millis(61_240))
+}
+
+fn stats(games: &[(u8, GameResult)]) -> Stats {
+    Stats { games: games.iter().map(|&(difficulty, result)| game(difficulty, result)).collect() }
+}
+
+#[test]
+fn games_are_appended_to_the_file() {
+    let path = stats_file("append");
+    assert_eq!(Stats::load(&path), Ok(Stats::default()));
+
+    let mut stats = Stats::default();
+    stats.record(&path, game(5, GameResult::Win)).unwrap();
+    let pop = GameRecord::new(&BitBoard::new(5, 6, 4).with_variant(Variant::PopOut), Piece::AI, &[Move::Pop(2); 30], 3, GameResult::Draw, Duration::from_secs(5));
+    stats.record(&path, pop.clone()).unwrap();
+
+    let loaded = Stats::load(&path).unwrap();
+    assert_eq!(loaded, stats);
+    assert_eq!((loaded.games[0].duration_secs, loaded.games[0].moves), (61.2, 21));
+    assert_eq!((loaded.games[1].variant.as_str(), loaded.games[1].rows, loaded.games[1].result), ("pop-out", 5, GameResult::Draw));
+    assert_eq!((loaded.games[1].player_started, loaded.games[1].moves), (false, 30));
+    assert!(loaded.games[1].history.starts_with("p2 p2 "));
+
+    // records from before replays have no moves, and still load
+    fs::write(&path, "[[game]]\nrows = 6\ncols = 7\nconnect = 4\nvariant = \"classic\"\nplayers = 2\ndifficulty = 4\nresult = \"loss\"\nmoves = 9\nduration_secs = 12.5\nfinished_at = 0\n").unwrap();
+    let old = Stats::load(&path).unwrap();
+    assert_eq!((old.games[0].player_started, old.games[0].history.as_str(), old.games[0].result), (true, "", GameResult::Loss));
+
+    fs::write(&path, "[[game]]\nrows = 6\n").unwrap();
+    assert!(Stats::load(&path).is_err());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn results_are_summed_per_difficulty() {
+    use GameResult::*;
+    let mut stats = stats(&[(3, Win), (3, Win), (3, Loss), (8, Draw), (8, Loss)]);
+    // games against more than one AI are kept, but not compared with the levels
+    let mut crowd = game(3, Loss);
+    crowd.players = 3;
+    stats.games.push(crowd);
+
+    let levels = stats.by_difficulty();
+    assert_eq!(levels.len(), 2);
+    assert_eq!((levels[0].difficulty, levels[0].wins, levels[0].losses, levels[0].games()), (3, 2, 1, 3));
+    assert!((levels[0].win_rate() - 2.0 / 3.0).abs() < 1e-9);
+    assert_eq!((levels[1].difficulty, levels[1].draws, levels[1].win_rate()), (8, 1, 0.0));
+
+    let report = stats.report();
+    assert!(report.starts_with("6 games played"), "{}", report);
+    assert!(report.contains("67%") && report.contains("Estimated rating"), "{}", report);
+    assert!(report.contains("  6. classic 6x7, difficulty 3, loss in 21 moves"), "{}", report);
+    assert_eq!(stats.recent(2).iter().map(|(number, _)| *number).collect::<Vec<_>>(), vec![6, 5]);
+    assert_eq!(Stats::default().report(), "No games played yet.");
+}
+
+#[test]
+fn rating_follows_the_results() {
+    use GameResult::*;
+    assert_eq!(Stats::default().rating(), None);
+
+    // an even score against one level is that level's rating
+    let even = stats(&[(5, Win), (5, Loss), (5, Draw)]).rating().unwrap();
+    assert!((even - level_rating(5)).abs() < 1.0, "{}", even);
+
+    let better = stats(&[(5, Win), (5, Win), (5, Loss)]).rating().unwrap();
+    let worse = stats(&[(5, Win), (5, Loss), (5, Loss)]).rating().unwrap();
+    assert!(worse < even && even < better, "{} {} {}", worse, even, better);
+
+    // only wins have no finite estimate, it stops 400 above the strongest level beaten
+    let unbeaten = stats(&[(2, Win), (4, Win)]).rating().unwrap();
+    assert!((unbeaten - (level_rating(4) + 400.0)).abs() < 1.0, "{}", unbeaten);
+}
+
+#[test]
+fn stats_command_and_file_on_the_command_line() {
+    let parsed = args(&["stats", "--stats-file", "games.toml"]).unwrap();
+    assert!(parsed.stats);
+    assert_eq!(parsed.stats_file, PathBuf::from("games.toml"));
+    assert!(!args(&[]).unwrap().stats);
+    assert!(args(&[]).unwrap().stats_file.ends_with(".connect-4-stats.toml") || std::env::var_os("CONNECT4_STATS").is_some());
+}
@@ -0,0 +1,105 @@
+use std::io::{Cursor, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use connect_4::params::EvalParams;
+use connect_4::uci::{run, EngineProtocol};
+
+// output that the test can still read after the engine has it
+#[derive(Clone, Default)]
+struct Output(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Output {
+    fn lines(&self) -> Vec<String> {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap().lines().map(str::to_string).collect()
+    }
+}
+
+fn session(script: &str) -> Vec<String> {
+    let output = Output::default();
+    run(Cursor::new(script.to_string()), output.clone(), EvalParams::default());
+    output.lines()
+}
+
+fn bestmove(lines: &[String]) -> &str {
+    lines.iter().rev().find_map(|l| l.strip_prefix("bestmove ")).expect("a bestmove line")
+}
+
+#[test]
+fn handshake_lists_the_options() {
+    let lines = session("uci\nisready\n");
+    assert!(lines[0].starts_with("id name connect-4"));
+    assert!(lines.iter().any(|l| l.starts_with("option name Variant") && l.contains("var cylinder")));
+    assert_eq!(&lines[lines.len() - 2..], ["uciok", "readyok"]);
+}
+
+#[test]
+fn finds_the_winning_move() {
+    // X has three in column 0, O to move must block, then X to move wins
+    let lines = session("position startpos moves 0 1 0 1 0\ngo depth 4\n");
+    assert_eq!(bestmove(&lines), "0");
+    assert!(lines.iter().any(|l| l.starts_with("info depth 1 ")));
+
+    let lines = session("position 0 1 0 1 0 6\ngo depth 2\n");
+    assert_eq!(bestmove(&lines), "0");
+    assert!(lines.iter().any(|l| l.contains("score win")));
+}
+
+#[test]
+fn movetime_and_stop_end_the_search() {
+    let start = Instant::now();
+    let lines = session("go movetime 100\n");
+    assert!(start.elapsed() < Duration::from_secs(3));
+    assert!(bestmove(&lines).parse::<u8>().is_ok());
+
+    let output = Output::default();
+    let mut engine = EngineProtocol::new(output.clone(), EvalParams::default());
+    engine.handle("go infinite");
+    std::thread::sleep(Duration::from_millis(50));
+    assert!(engine.handle("stop"));
+    assert!(bestmove(&output.lines()).parse::<u8>().is_ok());
+
+    // quit also ends a running search
+    engine.handle("go infinite");
+    assert!(!engine.handle("quit"));
+    assert_eq!(output.lines().iter().filter(|l| l.starts_with("bestmove")).count(), 2);
+}
+
+#[test]
+fn options_change_the_board_and_rules() {
+    let lines = session("setoption name Rows value 4\nsetoption name Cols value 5\nsetoption name Connect value 3\nd\n");
+    assert!(lines.iter().any(|l| l.trim() == "0  1  2  3  4"), "{:?}", lines);
+    assert!(lines.iter().any(|l| l == "Player to move"));
+
+    // pops need the Pop Out variant
+    let lines = session("position 0 p0\nsetoption name Variant value pop-out\nposition 0 1 p0\nd\n");
+    assert!(lines[0].starts_with("info string Illegal move p0"), "{:?}", lines);
+    assert_eq!(lines.iter().filter(|l| l.starts_with("info string")).count(), 1);
+
+    let lines = session("setoption name Players value 3\nposition 3 3\nd\n");
+    assert!(lines.iter().any(|l| l == "AI 2 to move"), "{:?}", lines);
+
+    let lines = session("setoption name center_weight value 5\nsetoption name Rows value 30\nsetoption name nope value 1\nsetoption Rows 4\nfly\ngo depth x\n");
+    let errors: Vec<&String> = lines.iter().filter(|l| l.starts_with("info string")).collect();
+    assert_eq!(errors.len(), 5, "{:?}", lines);
+}
+
+#[test]
+fn position_after_the_end_of_the_game_is_refused() {
+    let lines = session("position 3\nposition 0 1 0 1 0 1 0 1\nposition 4 9\nd\n");
+    assert!(lines[0].starts_with("info string Move 1 after the end of the game"), "{:?}", lines);
+    assert!(lines[1].starts_with("info string Illegal move 9"), "{:?}", lines);
+    // the position before the refused ones stays: one piece, the other side to move
+    assert!(lines.iter().any(|l| l == "AI to move"), "{:?}", lines);
+    assert_eq!(lines.iter().map(|l| l.matches('X').count()).sum::<usize>(), 1, "{:?}", lines);
+}
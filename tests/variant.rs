@@ -0,0 +1,281 @@
+mod common;
+
+use common::{args, naive_check_win, place};
+use connect_4::bitboard::{BitBoard, Move, Piece};
+use connect_4::minimax::evaluate_heuristic;
+use connect_4::params::EvalParams;
+use connect_4::search::Searcher;
+use connect_4::variant::Variant;
+use proptest::prelude::*;
+
+// 6x7 Pop 10 board filled without playing it out: the player has columns 0-2 and the bottom
+// half of column 3, the AI the rest
+fn filled_pop10() -> BitBoard {
+    let mut board = BitBoard::new(6, 7, 4).with_variant(Variant::Pop10);
+    for col in 0..7 {
+        for row in 0..6 {
+            let piece = if col < 3 || (col == 3 && row < 3) { Piece::Player } else { Piece::AI };
+            board = board.drop_piece(col, piece).unwrap();
+        }
+    }
+    board
+}
+
+#[test]
+fn variant_names_round_trip() {
+    for variant in Variant::ALL {
+        assert_eq!(variant.name().parse::<Variant>(), Ok(variant));
+        let (rows, cols, connect) = variant.default_size();
+        assert!(cols <= BitBoard::max_cols(rows) && connect <= rows.min(cols));
+    }
+    assert_eq!("Pop-Out".parse::<Variant>(), Ok(Variant::PopOut));
+    assert!("pop-20".parse::<Variant>().is_err());
+}
+
+#[test]
+fn variant_is_selected_on_the_command_line() {
+    assert_eq!(args(&[]).unwrap().variant, Variant::Classic);
+    assert_eq!(args(&["--variant", "pop-10"]).unwrap().variant, Variant::Pop10);
+    assert_eq!(args(&["--variant", "five-in-a-row"]).unwrap().variant, Variant::FiveInARow);
+    assert_eq!(args(&["--pop-out"]).unwrap().variant, Variant::PopOut);
+
+    let four = args(&["--variant", "connect-4x4"]).unwrap();
+    assert_eq!((four.variant, four.players), (Variant::Connect4x4, 4));
+    let err = args(&["--variant", "nope"]).unwrap_err();
+    assert!(err.contains("classic"), "{}", err);
+}
+
+#[test]
+fn classic_variants_have_no_pops() {
+    for variant in [Variant::Classic, Variant::FiveInARow] {
+        let board = BitBoard::new(6, 7, 4).with_variant(variant).drop_piece(0, Piece::Player).unwrap();
+        assert!(!board.can_pop(0, Piece::Player));
+        assert!(board.legal_moves(Piece::Player).iter().all(|mv| matches!(mv, Move::Drop(_))));
+    }
+}
+
+#[test]
+fn pop10_fills_the_board_before_popping() {
+    let mut board = BitBoard::new(2, 2, 2).with_variant(Variant::Pop10);
+    board = board.drop_piece(0, Piece::Player).unwrap().drop_piece(1, Piece::AI).unwrap();
+    board = board.drop_piece(0, Piece::Player).unwrap();
+
+    // a line does not end the game while filling
+    assert!(board.check_win(Piece::Player));
+    assert_eq!(board.winner(Piece::Player), None);
+    assert_eq!(board.legal_moves(Piece::AI), vec![Move::Drop(1)]);
+    assert!(!board.pop_phase());
+
+    let board = board.play(Move::Drop(1), Piece::AI).unwrap();
+    assert!(board.pop_phase());
+    assert_eq!(board.legal_moves(Piece::Player), vec![Move::Pop(0)]);
+    assert_eq!(board.legal_moves(Piece::AI), vec![Move::Pop(1)]);
+
+    // after a pop there is room again, but drops stay illegal
+    let popped = board.play(Move::Pop(0), Piece::Player).unwrap();
+    assert!(!popped.is_full());
+    assert!(popped.play(Move::Drop(0), Piece::AI).is_none());
+    assert!(popped.has_legal_move(Piece::AI));
+}
+
+#[test]
+fn pop10_keeps_pieces_from_lines_and_returns_others() {
+    let board = filled_pop10();
+
+    // column 0 is part of the player's lines - the piece is kept
+    let kept = board.play(Move::Pop(0), Piece::Player).unwrap();
+    assert_eq!(kept.kept(Piece::Player), 1);
+    assert_eq!(kept.height(0), 5);
+    assert_ne!(kept.position_key(), board.position_key());
+
+    // a lone piece at the bottom goes back on top of its column
+    let mut board = BitBoard::new(3, 3, 3).with_variant(Variant::Pop10);
+    for (col, piece) in [(0, Piece::Player), (0, Piece::AI), (0, Piece::AI), (1, Piece::AI), (1, Piece::Player),
+                         (1, Piece::Player), (2, Piece::Player), (2, Piece::AI), (2, Piece::AI)] {
+        board = board.drop_piece(col, piece).unwrap();
+    }
+    assert!(board.pop_phase());
+
+    let returned = board.play(Move::Pop(0), Piece::Player).unwrap();
+    assert_eq!(returned.kept(Piece::Player), 0);
+    assert_eq!(returned.height(0), 3);
+    assert_eq!(
+        (0..3).map(|row| returned.get_piece(row, 0)).collect::<Vec<_>>(),
+        vec![Piece::AI, Piece::AI, Piece::Player]
+    );
+}
+
+#[test]
+fn pop10_is_won_by_keeping_ten_pieces() {
+    let mut board = filled_pop10();
+    let mut piece = Piece::Player;
+    let mut winner = None;
+
+    // both sides pop a piece they can keep whenever there is one
+    for _ in 0..200 {
+        let moves = board.legal_moves(piece);
+        let keeps = |mv: &Move| board.play(*mv, piece).unwrap().kept(piece) > board.kept(piece);
+        let mv = match moves.iter().find(|mv| keeps(mv)).or(moves.first()) {
+            Some(&mv) => mv,
+            None => break,
+        };
+
+        board = board.play(mv, piece).unwrap();
+        winner = board.winner(piece);
+        if winner.is_some() {
+            break;
+        }
+        piece = piece.opponent();
+    }
+
+    let winner = winner.expect("someone keeps ten pieces");
+    assert_eq!(board.kept(winner), 10);
+    assert!(board.kept(winner.opponent()) < 10);
+}
+
+#[test]
+fn search_plays_pop10_pops() {
+    // the AI to move in the pop phase keeps a piece
+    let board = filled_pop10().play(Move::Pop(0), Piece::Player).unwrap();
+    let info = Searcher::new(EvalParams::default()).search(&board, 3, None, None);
+
+    let mv = info.best_move.unwrap();
+    assert!(matches!(mv, Move::Pop(_)));
+    assert_eq!(board.play(mv, Piece::AI).unwrap().kept(Piece::AI), 1);
+}
+
+#[test]
+fn misere_line_loses() {
+    let mut board = BitBoard::new(6, 7, 4).with_misere(true);
+    for _ in 0..4 {
+        board = board.drop_piece(0, Piece::Player).unwrap();
+    }
+    assert_eq!(board.winner(Piece::Player), Some(Piece::AI));
+
+    // lines for both after a pop - the player who popped loses
+    let mut board = BitBoard::new(6, 7, 4).with_variant(Variant::PopOut).with_misere(true);
+    for col in 0..3 {
+        board = board.drop_piece(col, Piece::AI).unwrap().drop_piece(col, Piece::Player).unwrap();
+    }
+    board = board.drop_piece(3, Piece::Player).unwrap().drop_piece(3, Piece::AI).unwrap().drop_piece(3, Piece::Player).unwrap();
+    let popped = board.play(Move::Pop(3), Piece::Player).unwrap();
+    assert_eq!(popped.winner(Piece::Player), Some(Piece::AI));
+
+    // Pop 10 is scored with kept pieces, the flag does not apply
+    assert!(!BitBoard::new(6, 7, 4).with_variant(Variant::Pop10).with_misere(true).misere);
+    assert!(args(&["--variant", "pop-10", "--misere"]).is_err());
+    assert!(args(&["--misere"]).unwrap().misere);
+}
+
+#[test]
+fn misere_search_avoids_completing_a_line() {
+    // the AI has three in the bottom row, dropping into column 3 would complete them
+    let mut board = BitBoard::new(6, 7, 4).with_misere(true);
+    for col in 0..3 {
+        board = board.drop_piece(col, Piece::AI).unwrap().drop_piece(col, Piece::Player).unwrap();
+    }
+    board = board.drop_piece(6, Piece::Player).unwrap();
+
+    let info = Searcher::new(EvalParams::default()).search(&board, 4, None, None);
+    let after = board.play(info.best_move.unwrap(), Piece::AI).unwrap();
+    assert!(!after.check_win(Piece::AI), "{}", board);
+    assert!(info.score > -100_000_000);
+
+    // the classic engine takes the line
+    let classic = board.clone().with_misere(false);
+    let info = Searcher::new(EvalParams::default()).search(&classic, 4, None, None);
+    assert_eq!(info.best_move, Some(Move::Drop(3)));
+}
+
+#[test]
+fn cylinder_lines_wrap_around() {
+    let lines: [[(u8, u8); 4]; 3] = [
+        [(0, 5), (0, 6), (0, 0), (0, 1)],   // horizontal
+        [(0, 5), (1, 6), (2, 0), (3, 1)],   // diagonal /
+        [(3, 5), (2, 6), (1, 0), (0, 1)],   // diagonal \
+    ];
+
+    for line in lines {
+        let mut board = BitBoard::new(6, 7, 4).with_variant(Variant::Cylinder);
+        for &(row, col) in &line {
+            place(&mut board, row, col, Piece::AI);
+        }
+        assert!(board.check_win(Piece::AI), "{:?}", line);
+        assert!(!board.clone().with_variant(Variant::Classic).check_win(Piece::AI));
+
+        let cells = board.line_cells(Piece::AI);
+        assert_eq!(cells.count_ones(), 4);
+        assert_eq!(cells, board.ai_mask);
+    }
+
+    // columns only wrap sideways - a diagonal does not continue over the top
+    let mut board = BitBoard::new(4, 7, 4).with_variant(Variant::Cylinder);
+    for (row, col) in [(2, 0), (3, 1), (0, 2), (1, 3)] {
+        place(&mut board, row, col, Piece::Player);
+    }
+    assert!(!board.check_win(Piece::Player));
+}
+
+#[test]
+fn threat_parity_follows_the_first_colour_after_pops() {
+    // the AI has three on the third row, the cell that completes it is not playable yet - an odd row,
+    // good for the colour that started
+    let mut board = BitBoard::new(6, 7, 4).with_variant(Variant::PopOut);
+    for (col, piece) in [(0, Piece::Player), (0, Piece::Player), (0, Piece::AI), (1, Piece::AI), (1, Piece::Player), (1, Piece::AI),
+                         (2, Piece::Player), (2, Piece::AI), (2, Piece::AI), (3, Piece::Player)] {
+        board = board.drop_piece(col, piece).unwrap();
+    }
+
+    // equal piece counts with the player to move: without pops the player started, after a pop it
+    // can just as well have been the AI
+    let params = EvalParams::default();
+    let ai_started = evaluate_heuristic(&board.clone().with_first(Piece::AI), Piece::AI, Piece::Player, &params);
+    let player_started = evaluate_heuristic(&board.clone().with_first(Piece::Player), Piece::AI, Piece::Player, &params);
+    assert_eq!(ai_started - player_started, params.threat_good_parity - params.threat_bad_parity);
+}
+
+#[test]
+fn cylinder_search_and_evaluation_see_the_seam() {
+    let mut board = BitBoard::new(6, 7, 4).with_variant(Variant::Cylinder);
+    for (col, piece) in [(6, Piece::AI), (3, Piece::Player), (0, Piece::AI), (3, Piece::Player), (1, Piece::AI), (4, Piece::Player)] {
+        board = board.drop_piece(col, piece).unwrap();
+    }
+
+    // three across the seam with both ends open
+    let params = EvalParams::default();
+    let classic = board.clone().with_variant(Variant::Classic);
+    assert!(
+        evaluate_heuristic(&board, Piece::AI, Piece::Player, &params)
+            > evaluate_heuristic(&classic, Piece::AI, Piece::Player, &params) + 50_000
+    );
+
+    let info = Searcher::new(params).search(&board, 3, None, None);
+    assert!(matches!(info.best_move, Some(Move::Drop(2)) | Some(Move::Drop(5))), "{:?}", info.best_move);
+    assert!(info.score >= 100_000_000);
+}
+
+fn cylinder_game() -> impl Strategy<Value = ((u8, u8, u8), Vec<usize>)> {
+    (2..=8u8)
+        .prop_flat_map(|rows| (Just(rows), 2..=BitBoard::max_cols(rows).min(12)))
+        .prop_flat_map(|(rows, cols)| (Just((rows, cols)), 2..=rows.min(cols)))
+        .prop_flat_map(|((rows, cols), connect)| {
+            (Just((rows, cols, connect)), prop::collection::vec(any::<usize>(), 0..=rows as usize * cols as usize))
+        })
+}
+
+proptest! {
+    #[test]
+    fn random_cylinder_games_match_naive_reference(((rows, cols, connect), moves) in cylinder_game()) {
+        let mut board = BitBoard::new(rows, cols, connect).with_variant(Variant::Cylinder);
+        let mut piece = Piece::Player;
+
+        for choice in moves {
+            let valid = board.get_valid_locations();
+            board = board.drop_piece(valid[choice % valid.len()], piece).unwrap();
+            piece = piece.opponent();
+
+            prop_assert_eq!(board.check_win(Piece::Player), naive_check_win(&board, Piece::Player), "{}", board);
+            prop_assert_eq!(board.check_win(Piece::AI), naive_check_win(&board, Piece::AI), "{}", board);
+        }
+    }
+}